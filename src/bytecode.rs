@@ -0,0 +1,816 @@
+//! The `.moxc` file format, a compiled script that can be run without parsing it again.
+//!
+//! ```text
+//! magic    "MOXC"
+//! version  u16
+//! function the top level script, see `write_function`
+//! checksum u32, crc-32 of everything before it
+//! ```
+//!
+//! every number is little endian, lengths and counts are u32
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::chunk::{Chunk, LineRun};
+use crate::heap::Heap;
+use crate::object::{Obj, ObjFunction, ObjRef};
+use crate::opcode::OpCode;
+use crate::value::{Value, ValueType};
+
+pub const MAGIC: &[u8; 4] = b"MOXC";
+/// bump whenever the layout or the meaning of an opcode changes
+pub const FORMAT_VERSION: u16 = 2;
+
+const TAG_NIL: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+
+/// how deeply functions may nest inside one another's constants, reading recurses once per level
+const MAX_NESTING: usize = 256;
+
+/// Why a compiled script could not be written or loaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+    /// the file doesn't start with `MAGIC`
+    NotBytecode,
+    /// the file was written by a different version of rustmox
+    Version { found: u16, expected: u16 },
+    /// the contents don't match their checksum
+    Checksum,
+    /// the file ends in the middle of something
+    Truncated,
+    /// the file is well formed but holds something that can't be in a chunk
+    Invalid(String),
+    /// the script was compiled by another vm, so its objects live in another heap
+    OtherVm,
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "Not a compiled mox script."),
+            BytecodeError::Version { found, expected } => write!(
+                f,
+                "Compiled for bytecode version {} but this rustmox reads version {}, compile the script again.",
+                found, expected
+            ),
+            BytecodeError::Checksum => write!(f, "Compiled script is corrupted, its checksum doesn't match."),
+            BytecodeError::Truncated => write!(f, "Compiled script ends unexpectedly."),
+            BytecodeError::Invalid(message) => write!(f, "Invalid compiled script: {}", message),
+            BytecodeError::OtherVm => write!(f, "The script was compiled by another vm."),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+/// whether `bytes` look like a compiled script rather than source
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// serializes a compiled top level script, nested functions travel in its constant pool
+pub fn serialize(function: ObjRef, heap: &Heap) -> Result<Vec<u8>, BytecodeError> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    write_function(&mut bytes, heap.as_function(function), heap)?;
+
+    let checksum = crc32(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    Ok(bytes)
+}
+
+/// loads a compiled script into `heap`, returning its top level function
+pub fn deserialize(bytes: &[u8], heap: &mut Heap) -> Result<ObjRef, BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::NotBytecode);
+    }
+    if bytes.len() < MAGIC.len() + 2 + 4 {
+        return Err(BytecodeError::Truncated);
+    }
+
+    // check the version first, an older layout may not even have a checksum where we expect one
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(BytecodeError::Version { found: version, expected: FORMAT_VERSION });
+    }
+
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    if crc32(body) != u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+        return Err(BytecodeError::Checksum);
+    }
+
+    let mut reader = Reader { bytes: body, position: MAGIC.len() + 2, depth: 0 };
+    let function = reader.function(heap)?;
+    if reader.position != body.len() {
+        return Err(BytecodeError::Invalid("trailing bytes after the script".to_string()));
+    }
+    // the vm runs the script as a closure with no arguments and nothing to capture
+    if function.arity != 0 || function.upvalue_count != 0 {
+        return Err(BytecodeError::Invalid("the script takes arguments or captures variables".to_string()));
+    }
+    Ok(heap.alloc(Obj::Function(function)))
+}
+
+/// name, arity, upvalue count, code, line runs and then the constant pool
+fn write_function(bytes: &mut Vec<u8>, function: &ObjFunction, heap: &Heap) -> Result<(), BytecodeError> {
+    match function.name {
+        Some(name) => {
+            bytes.push(1);
+            write_string(bytes, &heap.as_string(name).chars);
+        }
+        None => bytes.push(0),
+    }
+    write_u32(bytes, function.arity);
+    write_u32(bytes, function.upvalue_count);
+
+    let chunk = &function.chunk;
+    write_u32(bytes, chunk.code.len());
+    bytes.extend_from_slice(&chunk.code);
+
+    write_u32(bytes, chunk.lines.len());
+    for run in chunk.lines.iter() {
+        write_u32(bytes, run.start);
+        bytes.extend_from_slice(&run.line.to_le_bytes());
+    }
+
+    write_u32(bytes, chunk.constants.len());
+    for constant in chunk.constants.iter() {
+        write_constant(bytes, *constant, heap)?;
+    }
+    Ok(())
+}
+
+fn write_constant(bytes: &mut Vec<u8>, constant: Value, heap: &Heap) -> Result<(), BytecodeError> {
+    match constant.value {
+        ValueType::Nil => bytes.push(TAG_NIL),
+        ValueType::Bool(b) => {
+            bytes.push(TAG_BOOL);
+            bytes.push(b as u8);
+        }
+        ValueType::Number(n) => {
+            bytes.push(TAG_NUMBER);
+            bytes.extend_from_slice(&n.to_bits().to_le_bytes());
+        }
+        ValueType::Obj(reference) => match heap.get(reference) {
+            Obj::String(string) => {
+                bytes.push(TAG_STRING);
+                write_string(bytes, &string.chars);
+            }
+            Obj::Function(function) => {
+                bytes.push(TAG_FUNCTION);
+                write_function(bytes, function, heap)?;
+            }
+            obj => return Err(BytecodeError::Invalid(format!("can't store {:?} as a constant", obj))),
+        },
+    }
+    Ok(())
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    write_u32(bytes, string.len());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+/// reads back what `write_function` wrote
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    // functions being read around the current one
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], BytecodeError> {
+        let end = self.position.checked_add(count).ok_or(BytecodeError::Truncated)?;
+        let taken = self.bytes.get(self.position..end).ok_or(BytecodeError::Truncated)?;
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, BytecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, BytecodeError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn string(&mut self, heap: &mut Heap) -> Result<ObjRef, BytecodeError> {
+        let length = self.u32()?;
+        let chars = std::str::from_utf8(self.take(length)?)
+            .map_err(|_| BytecodeError::Invalid("string is not utf-8".to_string()))?;
+        Ok(heap.copy_string(chars))
+    }
+
+    fn function(&mut self, heap: &mut Heap) -> Result<ObjFunction, BytecodeError> {
+        let name = match self.byte()? {
+            0 => None,
+            _ => Some(self.string(heap)?),
+        };
+        let mut function = ObjFunction::new(name);
+        function.arity = self.u32()?;
+        function.upvalue_count = self.u32()?;
+
+        let mut chunk = Chunk::new();
+        let code_length = self.u32()?;
+        chunk.code = self.take(code_length)?.to_vec();
+
+        let run_count = self.u32()?;
+        for _ in 0..run_count {
+            let start = self.u32()?;
+            let line = self.u64()?;
+            chunk.lines.push(LineRun { start, line });
+        }
+
+        let constant_count = self.u32()?;
+        for _ in 0..constant_count {
+            let constant = self.constant(heap)?;
+            chunk.constants.push(constant);
+        }
+
+        function.chunk = chunk;
+        verify(&function, heap)?;
+        Ok(function)
+    }
+
+    fn constant(&mut self, heap: &mut Heap) -> Result<Value, BytecodeError> {
+        match self.byte()? {
+            TAG_NIL => Ok(Value::new()),
+            TAG_BOOL => Ok(Value::from_bool(self.byte()? != 0)),
+            TAG_NUMBER => Ok(Value::from_float(f64::from_bits(self.u64()?))),
+            TAG_STRING => Ok(Value::from_obj(self.string(heap)?)),
+            TAG_FUNCTION => {
+                if self.depth == MAX_NESTING {
+                    return Err(BytecodeError::Invalid("functions are nested too deeply".to_string()));
+                }
+                self.depth += 1;
+                let function = self.function(heap)?;
+                self.depth -= 1;
+                Ok(Value::from_obj(heap.alloc(Obj::Function(function))))
+            }
+            tag => Err(BytecodeError::Invalid(format!("unknown constant tag {}", tag))),
+        }
+    }
+}
+
+/// An instruction `verify` has decoded
+struct Decoded {
+    opcode: OpCode,
+    /// where the next instruction starts
+    next: usize,
+    /// the local slot, upvalue or argument count of the instruction, or where a jump lands
+    operand: usize,
+    /// whether each variable an `OpClosure` captures is a local, and its slot or upvalue
+    captures: Vec<(bool, usize)>,
+}
+
+/// checks that running `function` can't read past its code, constants, upvalues or stack
+/// frame, the vm trusts the compiler and skips these checks when it runs.
+/// what kind of value an instruction finds is left to the vm, which raises a runtime error
+/// when a method isn't a closure or a superclass isn't a class
+fn verify(function: &ObjFunction, heap: &Heap) -> Result<(), BytecodeError> {
+    let code = &function.chunk.code;
+    let mut instructions = Vec::new();
+    let mut starts = HashMap::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let instruction = decode(function, heap, offset)?;
+        starts.insert(offset, instructions.len());
+        offset = instruction.next;
+        instructions.push(instruction);
+    }
+
+    // walk every path through the code tracking how deep the stack is, so a local slot can
+    // be checked against the values that are really there
+    let mut depths: Vec<Option<usize>> = vec![None; instructions.len()];
+    let mut pending = vec![(0, function.arity + 1)];
+    while let Some((index, depth)) = pending.pop() {
+        let instruction = instructions.get(index).ok_or_else(|| invalid("code runs past the end of the chunk"))?;
+        match depths[index] {
+            Some(known) if known == depth => continue,
+            Some(_) => return Err(invalid("the stack has different depths on paths that meet")),
+            None => depths[index] = Some(depth),
+        }
+
+        let (pops, pushes) = stack_effect(instruction);
+        if depth < pops {
+            return Err(invalid("an instruction pops more values than the stack holds"));
+        }
+        let slot_fits = match instruction.opcode {
+            OpCode::OpGetLocal | OpCode::OpSetLocal => instruction.operand < depth,
+            OpCode::OpClosure | OpCode::OpClosureLong => {
+                instruction.captures.iter().all(|&(is_local, index)| !is_local || index < depth)
+            }
+            _ => true,
+        };
+        if !slot_fits {
+            return Err(invalid("a local slot is outside the stack frame"));
+        }
+
+        let depth = depth - pops + pushes;
+        let target = || {
+            starts
+                .get(&instruction.operand)
+                .copied()
+                .ok_or_else(|| invalid("a jump lands outside the code or inside an instruction"))
+        };
+        match instruction.opcode {
+            OpCode::OpReturn => {}
+            OpCode::OpJump | OpCode::OpLoop => pending.push((target()?, depth)),
+            OpCode::OpJumpIfFalse => {
+                pending.push((target()?, depth));
+                pending.push((index + 1, depth));
+            }
+            _ => pending.push((index + 1, depth)),
+        }
+    }
+
+    // jumps in code that can never run still have to land somewhere
+    for instruction in instructions.iter() {
+        if matches!(instruction.opcode, OpCode::OpJump | OpCode::OpJumpIfFalse | OpCode::OpLoop)
+            && !starts.contains_key(&instruction.operand)
+        {
+            return Err(invalid("a jump lands outside the code or inside an instruction"));
+        }
+    }
+    Ok(())
+}
+
+/// decodes the instruction at `offset`, checking its operands against the function
+fn decode(function: &ObjFunction, heap: &Heap, offset: usize) -> Result<Decoded, BytecodeError> {
+    let code = &function.chunk.code;
+    let constants = &function.chunk.constants;
+    let opcode = OpCode::try_from(code[offset])
+        .map_err(|byte| invalid(&format!("unknown opcode {} at offset {}", byte, offset)))?;
+
+    let mut next = offset + 1;
+    let mut byte = || {
+        let value = code.get(next).copied().ok_or_else(|| invalid("an operand is cut off by the end of the code"))?;
+        next += 1;
+        Ok::<usize, BytecodeError>(value as usize)
+    };
+
+    let mut operand = 0;
+    let mut captures = Vec::new();
+    match opcode {
+        OpCode::OpGetLocal | OpCode::OpSetLocal | OpCode::OpCall => operand = byte()?,
+        OpCode::OpGetUpvalue | OpCode::OpSetUpvalue => {
+            operand = byte()?;
+            if operand >= function.upvalue_count {
+                return Err(invalid("an upvalue index is out of range"));
+            }
+        }
+        OpCode::OpJump | OpCode::OpJumpIfFalse | OpCode::OpLoop => {
+            let jump = byte()? << 8 | byte()?;
+            operand = if opcode == OpCode::OpLoop {
+                (offset + 3).checked_sub(jump).ok_or_else(|| invalid("a loop jumps back before the code starts"))?
+            } else {
+                offset + 3 + jump
+            };
+        }
+        OpCode::OpNil
+        | OpCode::OpTrue
+        | OpCode::OpFalse
+        | OpCode::OpEqual
+        | OpCode::OpGreater
+        | OpCode::OpLess
+        | OpCode::OpAdd
+        | OpCode::OpSub
+        | OpCode::OpMult
+        | OpCode::OpDiv
+        | OpCode::OpMod
+        | OpCode::OpNot
+        | OpCode::OpNegate
+        | OpCode::OpPrint
+        | OpCode::OpPop
+        | OpCode::OpCloseUpvalue
+        | OpCode::OpInherit
+        | OpCode::OpReturn => {}
+        // everything else takes a constant index, possibly followed by more
+        _ => {
+            let constant = if opcode.is_long() { byte()? << 16 | byte()? << 8 | byte()? } else { byte()? };
+            let value = constants.get(constant).ok_or_else(|| invalid("a constant index is out of range"))?;
+            let object = value.as_obj().ok().map(|reference| (reference, heap.get(reference)));
+            match opcode {
+                OpCode::OpConstant | OpCode::OpConstantLong => {}
+                OpCode::OpClosure | OpCode::OpClosureLong => {
+                    let inner = match object {
+                        Some((_, Obj::Function(inner))) => inner,
+                        _ => return Err(invalid("a closure is made from something other than a function")),
+                    };
+                    for _ in 0..inner.upvalue_count {
+                        let is_local = match byte()? {
+                            0 => false,
+                            1 => true,
+                            _ => return Err(invalid("a captured variable is neither a local nor an upvalue")),
+                        };
+                        let index = byte()?;
+                        if !is_local && index >= function.upvalue_count {
+                            return Err(invalid("an upvalue index is out of range"));
+                        }
+                        captures.push((is_local, index));
+                    }
+                }
+                // the rest name a global, property, class or method
+                _ => {
+                    if !matches!(object, Some((_, Obj::String(_)))) {
+                        return Err(invalid("a name is not a string"));
+                    }
+                    if matches!(
+                        opcode,
+                        OpCode::OpInvoke | OpCode::OpInvokeLong | OpCode::OpSuperInvoke | OpCode::OpSuperInvokeLong
+                    ) {
+                        operand = byte()?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(Decoded { opcode, next, operand, captures })
+}
+
+/// how many values an instruction pops and then pushes
+fn stack_effect(instruction: &Decoded) -> (usize, usize) {
+    match instruction.opcode {
+        OpCode::OpConstant
+        | OpCode::OpConstantLong
+        | OpCode::OpNil
+        | OpCode::OpTrue
+        | OpCode::OpFalse
+        | OpCode::OpGetGlobal
+        | OpCode::OpGetGlobalLong
+        | OpCode::OpGetLocal
+        | OpCode::OpGetUpvalue
+        | OpCode::OpClosure
+        | OpCode::OpClosureLong
+        | OpCode::OpClass
+        | OpCode::OpClassLong => (0, 1),
+        OpCode::OpDefineGlobal
+        | OpCode::OpDefineGlobalLong
+        | OpCode::OpPrint
+        | OpCode::OpPop
+        | OpCode::OpCloseUpvalue
+        | OpCode::OpReturn => (1, 0),
+        OpCode::OpSetGlobal
+        | OpCode::OpSetGlobalLong
+        | OpCode::OpSetLocal
+        | OpCode::OpSetUpvalue
+        | OpCode::OpNot
+        | OpCode::OpNegate
+        | OpCode::OpGetProperty
+        | OpCode::OpGetPropertyLong
+        | OpCode::OpJumpIfFalse => (1, 1),
+        OpCode::OpEqual
+        | OpCode::OpGreater
+        | OpCode::OpLess
+        | OpCode::OpAdd
+        | OpCode::OpSub
+        | OpCode::OpMult
+        | OpCode::OpDiv
+        | OpCode::OpMod
+        | OpCode::OpSetProperty
+        | OpCode::OpSetPropertyLong
+        | OpCode::OpMethod
+        | OpCode::OpMethodLong
+        | OpCode::OpInherit
+        | OpCode::OpGetSuper
+        | OpCode::OpGetSuperLong => (2, 1),
+        OpCode::OpJump | OpCode::OpLoop => (0, 0),
+        // the callee or receiver and its arguments
+        OpCode::OpCall | OpCode::OpInvoke | OpCode::OpInvokeLong => (instruction.operand + 1, 1),
+        // the superclass sits on top of the receiver and its arguments
+        OpCode::OpSuperInvoke | OpCode::OpSuperInvokeLong => (instruction.operand + 2, 1),
+    }
+}
+
+fn invalid(message: &str) -> BytecodeError {
+    BytecodeError::Invalid(message.to_string())
+}
+
+/// crc-32 as used by zip and png
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::Disassembler;
+    use crate::vm::{InterpretResult, Settings, VM};
+
+    const SCRIPT: &str = "
+        class Greeter {
+            init(name) { this.name = name; }
+            greet() { return \"hello \" + this.name; }
+        }
+        func counter() {
+            dec count = 0;
+            func next() { count = count + 1; return count; }
+            return next;
+        }
+        dec next = counter();
+        next();
+        for (dec i = 0; i < 2; i = i + 1) { print Greeter(\"mox\").greet(); }
+        print next() + 0.5;
+        print -0.0;
+    ";
+
+    fn capturing_vm() -> VM {
+        VM::with_settings(Heap::new(), Settings::builder().capture_output().build())
+    }
+
+    fn compiled() -> Vec<u8> {
+        let mut vm = capturing_vm();
+        let function = vm.compile(SCRIPT.to_string()).unwrap();
+        serialize(function, vm.heap()).unwrap()
+    }
+
+    /// a file holding `function` as its script
+    fn file(function: ObjFunction) -> Vec<u8> {
+        let mut heap = Heap::new();
+        let function = heap.alloc(Obj::Function(function));
+        serialize(function, &heap).unwrap()
+    }
+
+    fn script(code: Vec<u8>, constants: Vec<Value>) -> ObjFunction {
+        let mut function = ObjFunction::new(None);
+        for byte in code {
+            function.chunk.write_chunk(byte, 1);
+        }
+        function.chunk.constants = constants;
+        function
+    }
+
+    /// replaces the checksum so only the change made to the contents is detected
+    fn reseal(mut bytes: Vec<u8>) -> Vec<u8> {
+        let checksum = crc32(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    fn invalid(bytes: &[u8]) -> bool {
+        matches!(deserialize(bytes, &mut Heap::new()), Err(BytecodeError::Invalid(_)))
+    }
+
+    #[test]
+    fn a_loaded_script_runs_like_the_compiled_one() {
+        let mut direct = capturing_vm();
+        let function = direct.compile(SCRIPT.to_string()).unwrap();
+        assert!(matches!(direct.interpret(function), InterpretResult::InterpretOk));
+
+        let mut loaded = capturing_vm();
+        let function = deserialize(&compiled(), loaded.heap_mut()).unwrap();
+        assert!(matches!(loaded.interpret(function), InterpretResult::InterpretOk));
+
+        let output = loaded.captured_output().unwrap();
+        assert_eq!(output, "hello mox\nhello mox\n2.5\n-0\n");
+        assert_eq!(direct.captured_output().unwrap(), output);
+    }
+
+    /// names more than 256 constants in the script and in a method, so every instruction
+    /// that names one needs its long form
+    fn long_script() -> String {
+        let mut source = String::new();
+        for i in 0..300 {
+            source += &format!("dec g{} = {};\n", i, i);
+        }
+        source += "g299 = g299 + 1;\nprint g299;\n";
+        source += "class A { get() { return \"a\"; } }\n";
+        source += "class B < A {\n  go() {\n";
+        for i in 0..300 {
+            source += &format!("    {}.5;\n", i);
+        }
+        source += "    print super.get();\n    dec get = super.get;\n    print get();\n  }\n}\n";
+        source += "dec b = B();\nb.field = \"f\";\nprint b.field;\nb.go();\n";
+        source
+    }
+
+    /// disassembles `function` and every function in its constants
+    fn disassembly(out: &mut Vec<u8>, function: ObjRef, heap: &Heap) {
+        let chunk = &heap.as_function(function).chunk;
+        Disassembler::disassemble_chunk(out, chunk, heap, String::new()).unwrap();
+        for constant in &chunk.constants {
+            if let ValueType::Obj(reference) = constant.value {
+                if let Obj::Function(_) = heap.get(reference) {
+                    disassembly(out, reference, heap);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_operands_run_the_same_once_loaded() {
+        let mut direct = capturing_vm();
+        let function = direct.compile(long_script()).unwrap();
+        let bytes = serialize(function, direct.heap()).unwrap();
+        assert!(matches!(direct.interpret(function), InterpretResult::InterpretOk));
+        assert_eq!(direct.captured_output().unwrap(), "300\nf\na\na\n");
+
+        let mut loaded = capturing_vm();
+        let function = deserialize(&bytes, loaded.heap_mut()).unwrap();
+        assert!(matches!(loaded.interpret(function), InterpretResult::InterpretOk));
+        assert_eq!(loaded.captured_output().unwrap(), "300\nf\na\na\n");
+    }
+
+    #[test]
+    fn every_long_form_disassembles_by_name() {
+        let mut vm = capturing_vm();
+        let function = vm.compile(long_script()).unwrap();
+        let mut out = Vec::new();
+        disassembly(&mut out, function, vm.heap());
+        let out = String::from_utf8(out).unwrap();
+
+        let long_forms = (0..=u8::MAX).filter_map(|byte| OpCode::try_from(byte).ok()).filter(|op| op.is_long());
+        for opcode in long_forms {
+            let name = format!("{:?}", opcode);
+            assert!(out.lines().any(|line| line.split_whitespace().any(|word| word == name)), "no {}", name);
+        }
+        assert!(!out.contains("Unknown opcode"));
+    }
+
+    #[test]
+    fn serializing_is_deterministic() {
+        assert_eq!(compiled(), compiled());
+    }
+
+    #[test]
+    fn rejects_another_version() {
+        let mut bytes = compiled();
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            deserialize(&bytes, &mut Heap::new()),
+            Err(BytecodeError::Version { found: FORMAT_VERSION + 1, expected: FORMAT_VERSION })
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut bytes = compiled();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        assert_eq!(deserialize(&bytes, &mut Heap::new()), Err(BytecodeError::Checksum));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let bytes = compiled();
+        assert_eq!(deserialize(&bytes[..7], &mut Heap::new()), Err(BytecodeError::Truncated));
+
+        let body = bytes[..bytes.len() / 2].to_vec();
+        assert_eq!(deserialize(&reseal(body), &mut Heap::new()), Err(BytecodeError::Truncated));
+    }
+
+    #[test]
+    fn rejects_source_code() {
+        assert_eq!(deserialize(b"print 1;", &mut Heap::new()), Err(BytecodeError::NotBytecode));
+    }
+
+    #[test]
+    fn accepts_a_minimal_script() {
+        let code = vec![OpCode::OpNil as u8, OpCode::OpReturn as u8];
+        assert!(deserialize(&file(script(code, Vec::new())), &mut Heap::new()).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_opcodes() {
+        assert!(invalid(&file(script(vec![0xff], Vec::new()))));
+    }
+
+    #[test]
+    fn rejects_a_cut_off_operand() {
+        let code = vec![OpCode::OpNil as u8, OpCode::OpReturn as u8, OpCode::OpConstantLong as u8, 0];
+        assert!(invalid(&file(script(code, vec![Value::new()]))));
+    }
+
+    #[test]
+    fn rejects_constants_outside_the_pool() {
+        let code = vec![OpCode::OpConstant as u8, 1, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, vec![Value::new()]))));
+
+        let code = vec![OpCode::OpConstantLong as u8, 1, 0, 0, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, vec![Value::new()]))));
+    }
+
+    #[test]
+    fn rejects_names_that_are_not_strings() {
+        let code = vec![OpCode::OpGetGlobal as u8, 0, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, vec![Value::from_float(1.0)]))));
+    }
+
+    #[test]
+    fn rejects_jumps_outside_the_code() {
+        let code = vec![OpCode::OpJump as u8, 0, 10, OpCode::OpNil as u8, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, Vec::new()))));
+
+        let code = vec![OpCode::OpNil as u8, OpCode::OpLoop as u8, 0, 10, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, Vec::new()))));
+
+        // into the middle of the loop's own operand
+        let code = vec![OpCode::OpNil as u8, OpCode::OpLoop as u8, 0, 2, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, Vec::new()))));
+    }
+
+    #[test]
+    fn rejects_slots_that_do_not_fit() {
+        // the script's frame only holds the script itself
+        let code = vec![OpCode::OpGetLocal as u8, 1, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, Vec::new()))));
+
+        let code = vec![OpCode::OpGetUpvalue as u8, 0, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, Vec::new()))));
+
+        let code = vec![OpCode::OpPop as u8, OpCode::OpPop as u8, OpCode::OpNil as u8, OpCode::OpReturn as u8];
+        assert!(invalid(&file(script(code, Vec::new()))));
+    }
+
+    #[test]
+    fn rejects_closures_capturing_more_than_the_enclosing_function_has() {
+        let mut inner = script(vec![OpCode::OpNil as u8, OpCode::OpReturn as u8], Vec::new());
+        inner.upvalue_count = 1;
+
+        let mut heap = Heap::new();
+        let inner = Value::from_obj(heap.alloc(Obj::Function(inner)));
+        // capture the enclosing function's first upvalue, but the script has none
+        let code = vec![OpCode::OpClosure as u8, 0, 0, 0, OpCode::OpReturn as u8];
+        let outer = heap.alloc(Obj::Function(script(code, vec![inner])));
+        assert!(invalid(&serialize(outer, &heap).unwrap()));
+    }
+
+    #[test]
+    fn rejects_a_script_with_parameters_or_upvalues() {
+        let code = vec![OpCode::OpNil as u8, OpCode::OpReturn as u8];
+        let mut function = script(code.clone(), Vec::new());
+        function.arity = 1;
+        assert!(invalid(&file(function)));
+
+        let mut function = script(code, Vec::new());
+        function.upvalue_count = 1;
+        assert!(invalid(&file(function)));
+    }
+
+    #[test]
+    fn rejects_functions_nested_too_deeply() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        // no name, no arity, upvalues, code or lines, and one constant: the next function
+        let level = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, TAG_FUNCTION];
+        for _ in 0..100_000 {
+            bytes.extend_from_slice(&level);
+        }
+        assert!(invalid(&reseal(bytes)));
+    }
+
+    /// runs `code` as a loaded script, which the verifier accepts because it balances the stack
+    fn run_loaded(code: Vec<u8>) -> InterpretResult {
+        let mut heap = Heap::new();
+        let name = Value::from_obj(heap.copy_string("m"));
+        let function = heap.alloc(Obj::Function(script(code, vec![name])));
+        let bytes = serialize(function, &heap).unwrap();
+
+        let mut vm = capturing_vm();
+        let function = deserialize(&bytes, vm.heap_mut()).unwrap();
+        vm.interpret(function)
+    }
+
+    #[test]
+    fn values_of_the_wrong_kind_are_runtime_errors() {
+        let (nil, class, pop, ret) = (OpCode::OpNil as u8, OpCode::OpClass as u8, OpCode::OpPop as u8, OpCode::OpReturn as u8);
+        let codes = vec![
+            vec![nil, nil, OpCode::OpMethod as u8, 0, pop, nil, ret],
+            vec![class, 0, nil, OpCode::OpMethod as u8, 0, pop, nil, ret],
+            vec![nil, nil, OpCode::OpInherit as u8, pop, nil, ret],
+            vec![class, 0, nil, OpCode::OpInherit as u8, pop, nil, ret],
+            vec![nil, nil, OpCode::OpGetSuper as u8, 0, pop, nil, ret],
+            vec![nil, nil, OpCode::OpSuperInvoke as u8, 0, 0, pop, nil, ret],
+        ];
+        for code in codes {
+            assert!(matches!(run_loaded(code), InterpretResult::InterpretRuntimeError(_)));
+        }
+    }
+}
@@ -1,8 +1,45 @@
-#[derive(Debug, Clone)]
+use std::collections::HashMap;
+
+use crate::object::ObjRef;
+use crate::value::{Value, ValueType};
+
+#[derive(Debug, Clone, Default)]
 pub struct Chunk {
     pub code: Vec<u8>,
-    pub constants: Vec<f64>,
-    pub lines: Vec<u64>,
+    pub constants: Vec<Value>,
+    /// one run per stretch of code compiled from the same line, ordered by `start`
+    pub lines: Vec<LineRun>,
+    // where each constant already sits in `constants`, so repeats share one slot
+    constant_slots: HashMap<ConstantKey, usize>,
+}
+
+/// The bytes from `start` up to the next run's start all came from `line`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRun {
+    pub start: usize,
+    pub line: u64,
+}
+
+/// How constants are compared when deduplicating.
+/// numbers compare by their bits, so NaN finds itself and 0.0 and -0.0 stay apart,
+/// strings are interned so comparing handles compares their contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Bool(bool),
+    Number(u64),
+    Obj(ObjRef),
+    Nil,
+}
+
+impl From<Value> for ConstantKey {
+    fn from(value: Value) -> Self {
+        match value.value {
+            ValueType::Bool(b) => ConstantKey::Bool(b),
+            ValueType::Number(n) => ConstantKey::Number(n.to_bits()),
+            ValueType::Obj(reference) => ConstantKey::Obj(reference),
+            ValueType::Nil => ConstantKey::Nil,
+        }
+    }
 }
 
 impl Chunk {
@@ -11,14 +48,81 @@ impl Chunk {
             code: Vec::new(),
             constants: Vec::new(),
             lines: Vec::new(),
+            constant_slots: HashMap::new(),
         }
     }
     pub fn write_chunk(&mut self, byte: u8, line: u64) {
+        if self.lines.last().is_none_or(|run| run.line != line) {
+            self.lines.push(LineRun { start: self.code.len(), line });
+        }
         self.code.push(byte);
-        self.lines.push(line);
     }
-    pub fn add_constant(&mut self, value: f64) -> u8 {
+    /// the source line the byte at `offset` was compiled from
+    pub fn line_at(&self, offset: usize) -> u64 {
+        // the run holding `offset` is the last one starting at or before it
+        let run = self.lines.partition_point(|run| run.start <= offset);
+        match run {
+            0 => 0,
+            run => self.lines[run - 1].line,
+        }
+    }
+    /// the index of `value` in the constant pool, adding it only if it isn't there yet
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        let key = ConstantKey::from(value);
+        if let Some(&slot) = self.constant_slots.get(&key) {
+            return slot;
+        }
         self.constants.push(value);
-        return (self.constants.len() - 1) as u8;
+        self.constant_slots.insert(key, self.constants.len() - 1);
+        self.constants.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::Heap;
+
+    #[test]
+    fn repeated_constants_share_a_slot() {
+        let mut heap = Heap::new();
+        let mut chunk = Chunk::new();
+
+        let one = chunk.add_constant(Value::from_float(1.0));
+        let name = chunk.add_constant(Value::from_obj(heap.copy_string("name")));
+        assert_eq!(chunk.add_constant(Value::from_float(1.0)), one);
+        // strings are interned, so a second copy finds the first one's slot
+        assert_eq!(chunk.add_constant(Value::from_obj(heap.copy_string("name"))), name);
+        assert_eq!(chunk.add_constant(Value::from_bool(true)), 2);
+        assert_eq!(chunk.add_constant(Value::new()), 3);
+        assert_eq!(chunk.add_constant(Value::from_bool(true)), 2);
+        assert_eq!(chunk.constants.len(), 4);
+    }
+
+    #[test]
+    fn zero_and_negative_zero_stay_apart() {
+        let mut chunk = Chunk::new();
+
+        let zero = chunk.add_constant(Value::from_float(0.0));
+        let negative_zero = chunk.add_constant(Value::from_float(-0.0));
+        assert_ne!(zero, negative_zero);
+        assert!(chunk.constants[negative_zero].as_float().unwrap().is_sign_negative());
+        assert_eq!(chunk.add_constant(Value::from_float(-0.0)), negative_zero);
+    }
+
+    #[test]
+    fn line_at_finds_the_run_holding_an_offset() {
+        assert_eq!(Chunk::new().line_at(0), 0);
+
+        let mut chunk = Chunk::new();
+        for &line in &[1, 1, 1, 4, 4, 7] {
+            chunk.write_chunk(0, line);
+        }
+        assert_eq!(chunk.lines.len(), 3);
+        assert_eq!(chunk.line_at(0), 1);
+        assert_eq!(chunk.line_at(2), 1);
+        assert_eq!(chunk.line_at(3), 4);
+        assert_eq!(chunk.line_at(4), 4);
+        assert_eq!(chunk.line_at(5), 7);
     }
 }
@@ -1,36 +1,177 @@
+use std::io::Write;
+
 use crate::chunk::Chunk;
+use crate::diagnostic::Diagnostic;
+use crate::heap::Heap;
+use crate::object::{Obj, ObjFunction, ObjRef};
 use crate::scanner::Scanner;
 use crate::parser::*;
-use crate::token::{TokenType};
+use crate::token::{Token, TokenType};
 use crate::opcode::OpCode;
 use crate::disassembler::Disassembler;
 use crate::value::*;
+
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+/// the largest index the long form of an instruction can address
+const MAX_LONG_CONSTANT: usize = (1 << 24) - 1;
+
+/// a local variable living in a stack slot.
+/// `depth` stays `None` until its initializer has been compiled
+struct Local {
+    name: String,
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+/// a variable captured by the function being compiled.
+/// `is_local` tells whether `index` is a local slot of the enclosing function or one of its upvalues
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+/// tracks the class whose body is being compiled, so `this` and `super` know where they are
+struct ClassCompiler {
+    has_superclass: bool,
+}
+
+/// the state of one function body being compiled.
+/// a function declared inside another one gets its own `FunctionCompiler`
+/// whose `enclosing` points back at the outer function
+struct FunctionCompiler {
+    enclosing: Option<Box<FunctionCompiler>>,
+    function: ObjFunction,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+}
+
+impl FunctionCompiler {
+    fn new(function_type: FunctionType, name: Option<ObjRef>) -> Self {
+        Self {
+            enclosing: None,
+            function: ObjFunction::new(name),
+            function_type,
+            locals: vec![Self::reserved_slot(function_type)],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+
+    /// slot zero belongs to the function being called.
+    /// methods store their receiver there and reach it through `this`
+    fn reserved_slot(function_type: FunctionType) -> Local {
+        let name = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this".to_string(),
+            FunctionType::Function | FunctionType::Script => String::new(),
+        };
+        Local { name, depth: Some(0), is_captured: false }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|local| local.name == name)
+    }
+
+    /// looks `name` up in the enclosing functions, capturing it in every function in between
+    fn resolve_upvalue(&mut self, name: &str) -> Result<Option<u8>, &'static str> {
+        let enclosing = match self.enclosing.as_mut() {
+            Some(enclosing) => enclosing,
+            None => return Ok(None),
+        };
+
+        if let Some(local) = enclosing.resolve_local(name) {
+            enclosing.locals[local].is_captured = true;
+            return self.add_upvalue(local as u8, true).map(Some);
+        }
+
+        match enclosing.resolve_upvalue(name)? {
+            Some(upvalue) => self.add_upvalue(upvalue, false).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(&mut self, index: u8, is_local: bool) -> Result<u8, &'static str> {
+        let upvalue = Upvalue { index, is_local };
+        if let Some(existing) = self.upvalues.iter().position(|&u| u == upvalue) {
+            return Ok(existing as u8);
+        }
+
+        if self.upvalues.len() == MAX_UPVALUES {
+            return Err("Too many closure variables in function.");
+        }
+        self.upvalues.push(upvalue);
+        self.function.upvalue_count = self.upvalues.len();
+        Ok((self.upvalues.len() - 1) as u8)
+    }
+}
+
 pub struct Compiler<'a> { 
     scanner: Scanner,
     parser: Parser,
-    chunk: &'a mut Chunk,
-    source: Vec<char>
+    heap: &'a mut Heap,
+    source: Vec<char>,
+    current: Box<FunctionCompiler>,
+    classes: Vec<ClassCompiler>,
+    diagnostics: Vec<Diagnostic>,
+    // echo bare top level expressions, set for code typed into the repl
+    repl: bool,
+    // where finished functions are disassembled to, if anywhere
+    code_output: Option<&'a mut dyn Write>,
 }
 
 impl<'a> Compiler<'a> {
-    pub fn from_source(source: String, chunk: &'a mut Chunk) -> Self {
+    pub fn from_source(source: String, heap: &'a mut Heap) -> Self {
         Self {
             // change scanner to take vector of characters 
             scanner: Scanner::new(&source),
             parser: Parser::new(),
-            chunk: chunk,
-            source: source.chars().collect()
+            heap,
+            source: source.chars().collect(),
+            current: Box::new(FunctionCompiler::new(FunctionType::Script, None)),
+            classes: Vec::new(),
+            diagnostics: Vec::new(),
+            repl: false,
+            code_output: None,
         }
     }
 
-    pub fn compile(&mut self) -> bool {
+    /// compiles a line typed into the repl, a bare expression prints its value
+    /// and the `;` after it is optional
+    pub fn repl(mut self) -> Self {
+        self.repl = true;
+        self
+    }
+
+    /// disassembles every function to `out` once it has been compiled
+    pub fn print_code(mut self, out: &'a mut dyn Write) -> Self {
+        self.code_output = Some(out);
+        self
+    }
+
+    /// compiles the whole source into the top level script function
+    pub fn compile(&mut self) -> Result<ObjRef, Vec<Diagnostic>> {
         
         self.advance();
-        self.expression();
-        self.consume(TokenType::EOF, "Expected end of expression");
-        self.end_compile();
+        while !self.match_token(TokenType::EOF) {
+            self.declaration();
+        }
+        let (function, _) = self.end_compile();
         
-        return !self.parser.had_error
+        if self.parser.had_error {
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+        Ok(self.heap.alloc(Obj::Function(function)))
     }
 
     fn advance(&mut self) {
@@ -49,7 +190,8 @@ impl<'a> Compiler<'a> {
             //     break
             // }
 
-            self.error_at_current();
+            let message = self.parser.current.message.clone().unwrap_or_default();
+            self.error_at_current(&message);
       
         }
     }
@@ -59,6 +201,414 @@ impl<'a> Compiler<'a> {
         self.parse_precedence(Precedence::ASSIGNMENT);
     }
 
+    fn declaration(&mut self) {
+        if self.match_token(TokenType::CLASS) {
+            self.class_declaration();
+        } else if self.match_token(TokenType::FUN) {
+            self.fun_declaration();
+        } else if self.match_token(TokenType::VAR) {
+            self.var_declaration();
+        } else {
+            self.statement();
+        }
+
+        if self.parser.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    /// skips tokens until something that looks like a statement boundary so the
+    /// next independent error can be reported as well
+    fn synchronize(&mut self) {
+        self.parser.panic_mode = false;
+
+        while self.parser.current.token_type != TokenType::EOF {
+            if self.parser.previous.token_type == TokenType::SEMICOLON {
+                return;
+            }
+            match self.parser.current.token_type {
+                TokenType::CLASS
+                | TokenType::FUN
+                | TokenType::VAR
+                | TokenType::FOR
+                | TokenType::IF
+                | TokenType::WHILE
+                | TokenType::PRINT
+                | TokenType::RETURN => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn class_declaration(&mut self) {
+        self.consume(TokenType::IDENTIFIER, "Expect class name.");
+        let class_name = self.lexeme(&self.parser.previous);
+        let name_constant = self.identifier_constant(&class_name);
+        self.declare_variable();
+
+        self.emit_constant_instruction(OpCode::OpClass, OpCode::OpClassLong, name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassCompiler { has_superclass: false });
+
+        if self.match_token(TokenType::LESS) {
+            self.consume(TokenType::IDENTIFIER, "Expect superclass name.");
+            let superclass_name = self.lexeme(&self.parser.previous);
+            self.named_variable(&superclass_name, false);
+
+            if superclass_name == class_name {
+                self.error("A class can't inherit from itself.");
+            }
+
+            // methods capture the superclass through a local named `super`
+            self.begin_scope();
+            self.add_local("super".to_string());
+            self.define_variable(0);
+
+            self.named_variable(&class_name, false);
+            self.emit_byte(OpCode::OpInherit as u8);
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        // keep the class on the stack while its methods are attached
+        self.named_variable(&class_name, false);
+        self.consume(TokenType::LEFT_BRACE, "Expect '{' before class body.");
+        while !self.check(TokenType::RIGHT_BRACE) && !self.check(TokenType::EOF) {
+            self.method();
+        }
+        self.consume(TokenType::RIGHT_BRACE, "Expect '}' after class body.");
+        self.emit_byte(OpCode::OpPop as u8);
+
+        if let Some(ClassCompiler { has_superclass: true }) = self.classes.pop() {
+            self.end_scope();
+        }
+    }
+
+    fn method(&mut self) {
+        self.consume(TokenType::IDENTIFIER, "Expect method name.");
+        let name = self.lexeme(&self.parser.previous);
+        let constant = self.identifier_constant(&name);
+
+        let function_type = if name == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        self.function(function_type);
+        self.emit_constant_instruction(OpCode::OpMethod, OpCode::OpMethodLong, constant);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        // a function may refer to itself, so it is usable before its body is compiled
+        self.mark_initialized();
+        self.function(FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, function_type: FunctionType) {
+        let name = self.lexeme(&self.parser.previous);
+        let name = self.heap.take_string(name);
+        self.push_compiler(function_type, Some(name));
+        self.begin_scope();
+
+        self.consume(TokenType::LEFT_PAREN, "Expect '(' after function name.");
+        if !self.check(TokenType::RIGHT_PAREN) {
+            loop {
+                self.current.function.arity += 1;
+                if self.current.function.arity > u8::MAX as usize {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+                let constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(constant);
+                if !self.match_token(TokenType::COMMA) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after parameters.");
+        self.consume(TokenType::LEFT_BRACE, "Expect '{' before function body.");
+        self.block();
+
+        // no end_scope, the frame is discarded as a whole when the function returns
+        let (function, upvalues) = self.end_compile();
+        let reference = self.heap.alloc(Obj::Function(function));
+        let constant = self.make_constant(Value::from_obj(reference));
+        self.emit_constant_instruction(OpCode::OpClosure, OpCode::OpClosureLong, constant);
+
+        for upvalue in upvalues {
+            self.emit_two_bytes(upvalue.is_local as u8, upvalue.index);
+        }
+    }
+
+    fn push_compiler(&mut self, function_type: FunctionType, name: Option<ObjRef>) {
+        let compiler = Box::new(FunctionCompiler::new(function_type, name));
+        let enclosing = std::mem::replace(&mut self.current, compiler);
+        self.current.enclosing = Some(enclosing);
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name.");
+
+        if self.match_token(TokenType::EQUAL) {
+            self.expression();
+        } else {
+            self.emit_byte(OpCode::OpNil as u8);
+        }
+        self.consume(TokenType::SEMICOLON, "Expect ';' after variable declaration.");
+
+        self.define_variable(global);
+    }
+
+    fn parse_variable(&mut self, message: &str) -> usize {
+        self.consume(TokenType::IDENTIFIER, message);
+
+        self.declare_variable();
+        if self.current.scope_depth > 0 {
+            return 0;
+        }
+
+        let name = self.lexeme(&self.parser.previous);
+        self.identifier_constant(&name)
+    }
+
+    /// records a local in the current scope, globals are late bound and need no declaring
+    fn declare_variable(&mut self) {
+        if self.current.scope_depth == 0 {
+            return;
+        }
+
+        let name = self.lexeme(&self.parser.previous);
+        for local in self.current.locals.iter().rev() {
+            if local.depth.is_some_and(|depth| depth < self.current.scope_depth) {
+                break;
+            }
+            if local.name == name {
+                self.error("Already a variable with this name in this scope.");
+                break;
+            }
+        }
+
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: String) {
+        if self.current.locals.len() == MAX_LOCALS {
+            self.error("Too many local variables in function.");
+            return;
+        }
+        self.current.locals.push(Local { name, depth: None, is_captured: false });
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.current.scope_depth == 0 {
+            return;
+        }
+        if let Some(local) = self.current.locals.last_mut() {
+            local.depth = Some(self.current.scope_depth);
+        }
+    }
+
+    /// stores an identifier's name in the constant table
+    fn identifier_constant(&mut self, name: &str) -> usize {
+        let reference = self.heap.copy_string(name);
+        self.make_constant(Value::from_obj(reference))
+    }
+
+    fn define_variable(&mut self, global: usize) {
+        if self.current.scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+        self.emit_constant_instruction(OpCode::OpDefineGlobal, OpCode::OpDefineGlobalLong, global);
+    }
+
+    fn resolve_local(&mut self, name: &str) -> Option<u8> {
+        let position = self.current.resolve_local(name)?;
+        if self.current.locals[position].depth.is_none() {
+            self.error("Can't read local variable in its own initializer.");
+        }
+        Some(position as u8)
+    }
+
+    fn resolve_upvalue(&mut self, name: &str) -> Option<u8> {
+        match self.current.resolve_upvalue(name) {
+            Ok(upvalue) => upvalue,
+            Err(message) => {
+                self.error(message);
+                Some(0)
+            }
+        }
+    }
+
+    fn statement(&mut self) {
+        if self.match_token(TokenType::PRINT) {
+            self.print_statement();
+        } else if self.match_token(TokenType::RETURN) {
+            self.return_statement();
+        } else if self.match_token(TokenType::IF) {
+            self.if_statement();
+        } else if self.match_token(TokenType::WHILE) {
+            self.while_statement();
+        } else if self.match_token(TokenType::FOR) {
+            self.for_statement();
+        } else if self.match_token(TokenType::LEFT_BRACE) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    fn block(&mut self) {
+        while !self.check(TokenType::RIGHT_BRACE) && !self.check(TokenType::EOF) {
+            self.declaration();
+        }
+        self.consume(TokenType::RIGHT_BRACE, "Expect '}' after block.");
+    }
+
+    fn begin_scope(&mut self) {
+        self.current.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current.scope_depth -= 1;
+
+        while let Some(local) = self.current.locals.last() {
+            if local.depth.is_none_or(|depth| depth <= self.current.scope_depth) {
+                break;
+            }
+            if local.is_captured {
+                self.emit_byte(OpCode::OpCloseUpvalue as u8);
+            } else {
+                self.emit_byte(OpCode::OpPop as u8);
+            }
+            self.current.locals.pop();
+        }
+    }
+
+    fn print_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::SEMICOLON, "Expect ';' after value.");
+        self.emit_byte(OpCode::OpPrint as u8);
+    }
+
+    fn return_statement(&mut self) {
+        if self.current.function_type == FunctionType::Script {
+            self.error("Can't return from top-level code.");
+        }
+
+        if self.match_token(TokenType::SEMICOLON) {
+            self.emit_return();
+        } else {
+            if self.current.function_type == FunctionType::Initializer {
+                self.error("Can't return a value from an initializer.");
+            }
+            self.expression();
+            self.consume(TokenType::SEMICOLON, "Expect ';' after return value.");
+            self.emit_byte(OpCode::OpReturn as u8);
+        }
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+        self.emit_byte(OpCode::OpPop as u8);
+        self.statement();
+
+        let else_jump = self.emit_jump(OpCode::OpJump);
+        self.patch_jump(then_jump);
+        self.emit_byte(OpCode::OpPop as u8);
+
+        if self.match_token(TokenType::ELSE) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.current_chunk().code.len();
+        self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+        self.emit_byte(OpCode::OpPop as u8);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OpCode::OpPop as u8);
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'for'.");
+        if self.match_token(TokenType::SEMICOLON) {
+            // no initializer
+        } else if self.match_token(TokenType::VAR) {
+            self.var_declaration();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.current_chunk().code.len();
+        let mut exit_jump = None;
+        if !self.match_token(TokenType::SEMICOLON) {
+            self.expression();
+            self.consume(TokenType::SEMICOLON, "Expect ';' after loop condition.");
+
+            exit_jump = Some(self.emit_jump(OpCode::OpJumpIfFalse));
+            self.emit_byte(OpCode::OpPop as u8);
+        }
+
+        // the increment runs after the body, so jump over it and loop back to it
+        if !self.match_token(TokenType::RIGHT_PAREN) {
+            let body_jump = self.emit_jump(OpCode::OpJump);
+            let increment_start = self.current_chunk().code.len();
+            self.expression();
+            self.emit_byte(OpCode::OpPop as u8);
+            self.consume(TokenType::RIGHT_PAREN, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_byte(OpCode::OpPop as u8);
+        }
+        self.end_scope();
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        if self.repl && self.current.function_type == FunctionType::Script && self.current.scope_depth == 0 {
+            let terminated = self.match_token(TokenType::SEMICOLON);
+            if self.check(TokenType::EOF) {
+                self.emit_byte(OpCode::OpPrint as u8);
+                return;
+            }
+            if terminated {
+                self.emit_byte(OpCode::OpPop as u8);
+                return;
+            }
+        }
+        self.consume(TokenType::SEMICOLON, "Expect ';' after expression.");
+        self.emit_byte(OpCode::OpPop as u8);
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) {
         // println!("{:?}", self.parser.current.token_type);
         if self.parser.current.token_type as usize == token_type as usize {
@@ -66,43 +616,119 @@ impl<'a> Compiler<'a> {
             return;
         }
         
-        self.parser.current.message = Some(message.to_string());
-        self.error_at_current()
+        self.error_at_current(message)
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        self.parser.current.token_type == token_type
+    }
+
+    fn match_token(&mut self, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+        self.advance();
+        true
     }
     
+    fn current_chunk(&mut self) -> &mut Chunk {
+        &mut self.current.function.chunk
+    }
+
     fn emit_byte(&mut self, byte: u8) {
-        
-        self.chunk.write_chunk(byte, self.parser.previous.line);
+        let line = self.parser.previous.line;
+        self.current_chunk().write_chunk(byte, line);
     }
     fn emit_two_bytes(&mut self, byte1: u8, byte2: u8) {
         self.emit_byte(byte1);
         self.emit_byte(byte2);
     }
 
-    fn end_compile(&mut self){
+    /// emits a jump with a placeholder operand and returns where the operand lives
+    fn emit_jump(&mut self, instruction: OpCode) -> usize {
+        self.emit_byte(instruction as u8);
+        self.emit_two_bytes(0xff, 0xff);
+        self.current_chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to account for the jump operand itself
+        let jump = self.current_chunk().code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+        }
+
+        self.current_chunk().code[offset] = ((jump >> 8) & 0xff) as u8;
+        self.current_chunk().code[offset + 1] = (jump & 0xff) as u8;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_byte(OpCode::OpLoop as u8);
+
+        let offset = self.current_chunk().code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.error("Loop body too large.");
+        }
+        self.emit_two_bytes(((offset >> 8) & 0xff) as u8, (offset & 0xff) as u8);
+    }
+
+    /// finishes the current function and hands control back to the enclosing compiler
+    fn end_compile(&mut self) -> (ObjFunction, Vec<Upvalue>) {
         self.emit_return();
 
-        Disassembler::disassemble_chunk(self.chunk, "Compiled Chunk".to_string());
+        if let Some(out) = self.code_output.as_mut() {
+            if !self.parser.had_error {
+                let name = match self.current.function.name {
+                    Some(name) => self.heap.as_string(name).chars.clone(),
+                    None => "<script>".to_string(),
+                };
+                // the dump is only a debugging aid, a failing writer shouldn't fail the compile
+                let _ = Disassembler::disassemble_chunk(&mut **out, &self.current.function.chunk, self.heap, name);
+            }
+        }
+
+        match self.current.enclosing.take() {
+            Some(enclosing) => {
+                let finished = std::mem::replace(&mut self.current, enclosing);
+                (finished.function, finished.upvalues)
+            }
+            None => (std::mem::take(&mut self.current.function), Vec::new()),
+        }
     }
 
+    /// an implicit `return nil;`, initializers return the instance instead
     fn emit_return(&mut self) {
+        if self.current.function_type == FunctionType::Initializer {
+            self.emit_two_bytes(OpCode::OpGetLocal as u8, 0);
+        } else {
+            self.emit_byte(OpCode::OpNil as u8);
+        }
         self.emit_byte(OpCode::OpReturn as u8);
     }
     fn emit_constant(&mut self, value: Value) {
-        if let ValueType::Number(n) = value.value {
-            let byte2 = self.make_constant(n);
-            self.emit_two_bytes(OpCode::OpConstant as u8, byte2)
+        let constant = self.make_constant(value);
+        self.emit_constant_instruction(OpCode::OpConstant, OpCode::OpConstantLong, constant);
+    }
+    /// emits `short` with a one byte constant index, switching to its long form once the
+    /// one byte indices run out
+    fn emit_constant_instruction(&mut self, short: OpCode, long: OpCode, constant: usize) {
+        if constant <= u8::MAX as usize {
+            self.emit_two_bytes(short as u8, constant as u8);
+        } else {
+            self.emit_byte(long as u8);
+            self.emit_byte((constant >> 16) as u8);
+            self.emit_byte((constant >> 8) as u8);
+            self.emit_byte(constant as u8);
         }
-       
     }
-    fn make_constant(&mut self, value: f64) -> u8{
-        let constant = self.chunk.add_constant(value);
-        if constant > std::u8::MAX {
-            eprintln!("Too many constants in one chunk");
+    fn make_constant(&mut self, value: Value) -> usize {
+        let constant = self.current_chunk().add_constant(value);
+        if constant > MAX_LONG_CONSTANT {
+            self.error("Too many constants in one chunk.");
             return 0
         }
 
-        return constant
+        constant
     }
 
     fn handle_number(&mut self ){
@@ -119,10 +745,150 @@ impl<'a> Compiler<'a> {
         self.emit_constant(value_type);
     }
 
+    fn handle_string(&mut self) {
+        // trim the surrounding quotes
+        let start = self.parser.previous.start + 1;
+        let end = self.parser.previous.start + self.parser.previous.length - 1;
+        let string: String = self.source[start..end].iter().collect();
+
+        let reference = self.heap.take_string(string);
+        self.emit_constant(Value::from_obj(reference));
+    }
+
+    fn handle_variable(&mut self, can_assign: bool) {
+        let name = self.lexeme(&self.parser.previous);
+        self.named_variable(&name, can_assign);
+    }
+
+    fn handle_this(&mut self) {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class.");
+            return;
+        }
+        self.named_variable("this", false);
+    }
+
+    fn handle_super(&mut self) {
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class."),
+            Some(class) if !class.has_superclass => self.error("Can't use 'super' in a class with no superclass."),
+            Some(_) => {}
+        }
+
+        self.consume(TokenType::DOT, "Expect '.' after 'super'.");
+        self.consume(TokenType::IDENTIFIER, "Expect superclass method name.");
+        let name = self.lexeme(&self.parser.previous);
+        let name = self.identifier_constant(&name);
+
+        self.named_variable("this", false);
+        if self.match_token(TokenType::LEFT_PAREN) {
+            let arg_count = self.argument_list();
+            self.named_variable("super", false);
+            self.emit_constant_instruction(OpCode::OpSuperInvoke, OpCode::OpSuperInvokeLong, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.named_variable("super", false);
+            self.emit_constant_instruction(OpCode::OpGetSuper, OpCode::OpGetSuperLong, name);
+        }
+    }
+
+    fn named_variable(&mut self, name: &str, can_assign: bool) {
+        let (get_op, set_op, arg) = if let Some(slot) = self.resolve_local(name) {
+            (OpCode::OpGetLocal, OpCode::OpSetLocal, slot)
+        } else if let Some(upvalue) = self.resolve_upvalue(name) {
+            (OpCode::OpGetUpvalue, OpCode::OpSetUpvalue, upvalue)
+        } else {
+            let constant = self.identifier_constant(name);
+            if can_assign && self.match_token(TokenType::EQUAL) {
+                self.expression();
+                self.emit_constant_instruction(OpCode::OpSetGlobal, OpCode::OpSetGlobalLong, constant);
+            } else {
+                self.emit_constant_instruction(OpCode::OpGetGlobal, OpCode::OpGetGlobalLong, constant);
+            }
+            return;
+        };
+
+        if can_assign && self.match_token(TokenType::EQUAL) {
+            self.expression();
+            self.emit_two_bytes(set_op as u8, arg);
+        } else {
+            self.emit_two_bytes(get_op as u8, arg);
+        }
+    }
+
+    fn handle_call(&mut self) {
+        let arg_count = self.argument_list();
+        self.emit_two_bytes(OpCode::OpCall as u8, arg_count);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: usize = 0;
+        if !self.check(TokenType::RIGHT_PAREN) {
+            loop {
+                self.expression();
+                if arg_count == u8::MAX as usize {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                arg_count += 1;
+                if !self.match_token(TokenType::COMMA) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after arguments.");
+        arg_count as u8
+    }
+
+    fn handle_dot(&mut self, can_assign: bool) {
+        self.consume(TokenType::IDENTIFIER, "Expect property name after '.'.");
+        let name = self.lexeme(&self.parser.previous);
+        let name = self.identifier_constant(&name);
+
+        if can_assign && self.match_token(TokenType::EQUAL) {
+            self.expression();
+            self.emit_constant_instruction(OpCode::OpSetProperty, OpCode::OpSetPropertyLong, name);
+        } else if self.match_token(TokenType::LEFT_PAREN) {
+            // `obj.method(args)` calls straight through without creating a bound method
+            let arg_count = self.argument_list();
+            self.emit_constant_instruction(OpCode::OpInvoke, OpCode::OpInvokeLong, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.emit_constant_instruction(OpCode::OpGetProperty, OpCode::OpGetPropertyLong, name);
+        }
+    }
+
+    fn handle_and(&mut self) {
+        let end_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+
+        self.emit_byte(OpCode::OpPop as u8);
+        self.parse_precedence(Precedence::AND);
+
+        self.patch_jump(end_jump);
+    }
+
+    fn handle_or(&mut self) {
+        let else_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::OpJump);
+
+        self.patch_jump(else_jump);
+        self.emit_byte(OpCode::OpPop as u8);
+
+        self.parse_precedence(Precedence::OR);
+        self.patch_jump(end_jump);
+    }
+
     fn handle_grouping(&mut self) {
         self.expression();
-        // this needs to be fixed
-        // self.consume(TokenType::RIGHT_PAREN, "Expected ')' after expression");
+        self.consume(TokenType::RIGHT_PAREN, "Expected ')' after expression");
+    }
+
+    fn handle_literal(&mut self) {
+        match self.parser.previous.token_type {
+            TokenType::FALSE => self.emit_byte(OpCode::OpFalse as u8),
+            TokenType::NIL => self.emit_byte(OpCode::OpNil as u8),
+            TokenType::TRUE => self.emit_byte(OpCode::OpTrue as u8),
+            _ => {}
+        }
     }
 
     fn handle_unary(&mut self) {
@@ -131,7 +897,8 @@ impl<'a> Compiler<'a> {
       
         match token_type {
             TokenType::MINUS => self.emit_byte(OpCode::OpNegate as u8),
-            _ => {return}
+            TokenType::BANG => self.emit_byte(OpCode::OpNot as u8),
+            _ => {}
         }
     }
 
@@ -140,7 +907,7 @@ impl<'a> Compiler<'a> {
         let operator_type = self.parser.previous.token_type;
         let rule = self.get_rule(operator_type);
 
-        let precedence = unsafe { std::mem::transmute((rule.precedence as u8 + 1) as u8) };
+        let precedence = unsafe { std::mem::transmute::<u8, Precedence>(rule.precedence as u8 + 1) };
      
         self.parse_precedence(precedence);
      
@@ -151,7 +918,13 @@ impl<'a> Compiler<'a> {
             TokenType::STAR  => self.emit_byte(OpCode::OpMult as u8),
             TokenType::SLASH => self.emit_byte(OpCode::OpDiv as u8),
             TokenType::MOD => self.emit_byte(OpCode::OpMod as u8),
-            _ => {return } 
+            TokenType::BANG_EQUAL => self.emit_two_bytes(OpCode::OpEqual as u8, OpCode::OpNot as u8),
+            TokenType::EQUAL_EQUAL => self.emit_byte(OpCode::OpEqual as u8),
+            TokenType::GREATER => self.emit_byte(OpCode::OpGreater as u8),
+            TokenType::GREATER_EQUAL => self.emit_two_bytes(OpCode::OpLess as u8, OpCode::OpNot as u8),
+            TokenType::LESS => self.emit_byte(OpCode::OpLess as u8),
+            TokenType::LESS_EQUAL => self.emit_two_bytes(OpCode::OpGreater as u8, OpCode::OpNot as u8),
+            _ => {} 
         }
     }
 
@@ -161,20 +934,27 @@ impl<'a> Compiler<'a> {
    
         let token_type = self.parser.previous.token_type;
         let rule = self.get_rule(token_type);
-       
+        let can_assign = precedence as u8 <= Precedence::ASSIGNMENT as u8;
        
         match rule.prefix {
             ParseFunctions::Binary => {  self.handle_binary() },
             ParseFunctions::Unary =>  { self.handle_unary() },
             ParseFunctions::Number => {  self.handle_number()},
+            ParseFunctions::String => { self.handle_string() },
             ParseFunctions::Grouping => { self.handle_grouping() },
-            _ => { return }
+            ParseFunctions::Literal => { self.handle_literal() },
+            ParseFunctions::Variable => { self.handle_variable(can_assign) },
+            ParseFunctions::This => { self.handle_this() },
+            ParseFunctions::Super => { self.handle_super() },
+            _ => {
+                self.error("Expect expression.");
+                return
+            }
         }
      
 
-        let current_token_type = self.parser.current.token_type;
         // println!("precedence: {:?}, current_token_precedence: {:?}, {:?} ", precedence, self.get_rule(current_token_type), self.parser.current.token_type );
-        while precedence as u8 <= self.get_rule(current_token_type).precedence as u8 {
+        while precedence as u8 <= self.get_rule(self.parser.current.token_type).precedence as u8 {
             self.advance();
           
             let prev_token_type = self.parser.previous.token_type;
@@ -184,42 +964,52 @@ impl<'a> Compiler<'a> {
                 ParseFunctions::Unary =>  { self.handle_unary() },
                 ParseFunctions::Number => {  self.handle_number()},
                 ParseFunctions::Grouping => { self.handle_grouping() },
+                ParseFunctions::Literal => { self.handle_literal() },
+                ParseFunctions::And => { self.handle_and() },
+                ParseFunctions::Or => { self.handle_or() },
+                ParseFunctions::Call => { self.handle_call() },
+                ParseFunctions::Dot => { self.handle_dot(can_assign) },
                 _ => { return }
             }
         }  
+
+        if can_assign && self.match_token(TokenType::EQUAL) {
+            self.error("Invalid assignment target.");
+        }
        
     }
 
+    fn lexeme(&self, token: &Token) -> String {
+        self.source[token.start..token.start + token.length].iter().collect()
+    }
+
     fn get_rule(&self, token_type: TokenType) ->ParseRule{
         // println!("{}", token_type as usize);
         self.parser.parse_rules[token_type as usize]
     }
 
-    fn error_at_current(&mut self) {
+    fn error_at_current(&mut self, message: &str) {
+        let token = self.parser.current.clone();
+        self.error_at(&token, message);
+    }
+
+    /// reports an error at the token we just consumed
+    fn error(&mut self, message: &str) {
+        let token = self.parser.previous.clone();
+        self.error_at(&token, message);
+    }
+
+    fn error_at(&mut self, token: &Token, message: &str) {
         if self.parser.panic_mode {
             return
         }
         self.parser.panic_mode = true;
-    
-        let mut string = format!("{}: Error ", self.parser.current.line);
-        match self.parser.current.token_type {
-            TokenType::EOF => {
-                string.push_str(" at end");
-            },
-            TokenType::ERROR => {
-                string.push_str(" at end");
-            }
-            _ => {
-                
-                string.push_str(&format!("at {}", self.parser.current.start));
-            }
-        }
 
-        if let Some(err_msg) = &self.parser.current.message {
-            string.push_str(&format!("\n {}", err_msg));
+        let mut diagnostic = Diagnostic::error(token.span(), message);
+        if token.token_type == TokenType::EOF {
+            diagnostic = diagnostic.with_note("reached the end of the file");
         }
-
-        eprintln!("{}", string);
+        self.diagnostics.push(diagnostic);
 
         self.parser.had_error = true;
     }
@@ -0,0 +1,177 @@
+//! Conversions between Rust values and script values, used when embedding rustmox.
+
+use std::collections::HashMap;
+
+use crate::embed::Handle;
+use crate::heap::Heap;
+use crate::object::{Obj, ObjList, ObjMap};
+use crate::value::{Value, ValueType};
+use crate::vm::RuntimeError;
+
+/// A Rust value that can be handed to a script.
+/// conversions allocate straight into the heap and never trigger a collection,
+/// they only fail for a `Handle` that belongs to another vm
+pub trait IntoValue {
+    fn into_value(self, heap: &mut Heap) -> Result<Value, RuntimeError>;
+}
+
+/// A Rust value that can be read back out of a script value
+pub trait FromValue: Sized {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, RuntimeError>;
+}
+
+/// what kind of value `value` is, for conversion errors
+fn type_name(value: Value, heap: &Heap) -> &'static str {
+    match value.value {
+        ValueType::Nil => "nil",
+        ValueType::Bool(_) => "bool",
+        ValueType::Number(_) => "number",
+        ValueType::Obj(reference) => match heap.get(reference) {
+            Obj::String(_) => "string",
+            Obj::List(_) => "list",
+            Obj::Map(_) => "map",
+            Obj::Instance(_) => "instance",
+            Obj::Class(_) => "class",
+            _ => "function",
+        },
+    }
+}
+
+fn mismatch(expected: &str, value: Value, heap: &Heap) -> RuntimeError {
+    RuntimeError::new(&format!("Expected a {} but got {}.", expected, type_name(value, heap)))
+}
+
+impl IntoValue for Value {
+    fn into_value(self, _heap: &mut Heap) -> Result<Value, RuntimeError> {
+        Ok(self)
+    }
+}
+
+impl IntoValue for Handle {
+    fn into_value(self, heap: &mut Heap) -> Result<Value, RuntimeError> {
+        self.value(heap)
+    }
+}
+
+impl IntoValue for &Handle {
+    fn into_value(self, heap: &mut Heap) -> Result<Value, RuntimeError> {
+        self.value(heap)
+    }
+}
+
+/// there is no `FromValue` for a bare `Value`, nothing would keep it alive once it is returned
+impl FromValue for Handle {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, RuntimeError> {
+        Ok(Handle::new(value, heap))
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self, _heap: &mut Heap) -> Result<Value, RuntimeError> {
+        Ok(Value::from_float(self))
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, RuntimeError> {
+        value.as_float().map_err(|_| mismatch("number", value, heap))
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self, _heap: &mut Heap) -> Result<Value, RuntimeError> {
+        Ok(Value::from_bool(self))
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, RuntimeError> {
+        value.as_bool().map_err(|_| mismatch("bool", value, heap))
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self, heap: &mut Heap) -> Result<Value, RuntimeError> {
+        Ok(Value::from_obj(heap.take_string(self)))
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self, heap: &mut Heap) -> Result<Value, RuntimeError> {
+        Ok(Value::from_obj(heap.copy_string(self)))
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, RuntimeError> {
+        if !value.is_string(heap) {
+            return Err(mismatch("string", value, heap));
+        }
+        let reference = value.as_obj().map_err(RuntimeError::new)?;
+        Ok(heap.as_string(reference).chars.clone())
+    }
+}
+
+/// `None` is nil
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self, heap: &mut Heap) -> Result<Value, RuntimeError> {
+        match self {
+            Some(value) => value.into_value(heap),
+            None => Ok(Value::new()),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, RuntimeError> {
+        match value.value {
+            ValueType::Nil => Ok(None),
+            _ => T::from_value(value, heap).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self, heap: &mut Heap) -> Result<Value, RuntimeError> {
+        let items = self.into_iter().map(|item| item.into_value(heap)).collect::<Result<_, _>>()?;
+        Ok(Value::from_obj(heap.alloc(Obj::List(ObjList::new(items)))))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, RuntimeError> {
+        match value.value {
+            ValueType::Obj(reference) if matches!(heap.get(reference), Obj::List(_)) => heap
+                .as_list(reference)
+                .items
+                .iter()
+                .map(|&item| T::from_value(item, heap))
+                .collect(),
+            _ => Err(mismatch("list", value, heap)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self, heap: &mut Heap) -> Result<Value, RuntimeError> {
+        let entries = self
+            .into_iter()
+            .map(|(key, value)| Ok((heap.take_string(key), value.into_value(heap)?)))
+            .collect::<Result<_, RuntimeError>>()?;
+        Ok(Value::from_obj(heap.alloc(Obj::Map(ObjMap::new(entries)))))
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: Value, heap: &Heap) -> Result<Self, RuntimeError> {
+        match value.value {
+            ValueType::Obj(reference) if matches!(heap.get(reference), Obj::Map(_)) => heap
+                .as_map(reference)
+                .entries
+                .iter()
+                .map(|(&key, &value)| Ok((heap.as_string(key).chars.clone(), T::from_value(value, heap)?)))
+                .collect(),
+            _ => Err(mismatch("map", value, heap)),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Compile errors, collected by the compiler and rendered with a snippet of the source.
+
+use std::fmt;
+
+use crate::token::Span;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Severity {
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: &str) -> Self {
+        Self {
+            severity: Severity::Error,
+            span,
+            message: message.to_string(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// renders the diagnostic the way rustc does, `name` is the file the source came from
+    ///
+    /// ```text
+    /// error: Expect ';' after value.
+    ///  --> script.mox:1:8
+    ///   |
+    /// 1 | print 1
+    ///   |        ^
+    /// ```
+    pub fn render(&self, name: &str, source: &str) -> String {
+        let (line, column) = (self.span.line as usize, self.span.column);
+        let source_line = source
+            .lines()
+            .nth(line - 1)
+            .unwrap_or("")
+            .trim_end_matches('\0');
+
+        // underline the whole span, but never past the end of its first line
+        let line_length = source_line.chars().count();
+        let span_length = self.span.end.saturating_sub(self.span.start);
+        let underline = span_length.min(line_length.saturating_sub(column - 1)).max(1);
+
+        let gutter = " ".repeat(line.to_string().len());
+        let mut rendered = format!("{}: {}\n", self.severity, self.message);
+        rendered.push_str(&format!("{}--> {}:{}:{}\n", gutter, name, line, column));
+        rendered.push_str(&format!("{} |\n", gutter));
+        rendered.push_str(&format!("{} | {}\n", line, source_line));
+        rendered.push_str(&format!("{} | {}{}\n", gutter, " ".repeat(column - 1), "^".repeat(underline)));
+        for note in self.notes.iter() {
+            rendered.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+        rendered
+    }
+}
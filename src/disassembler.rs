@@ -1,57 +1,201 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
 use crate::chunk::Chunk;
+use crate::heap::Heap;
 use crate::opcode::OpCode;
+use crate::value::ValueType;
 
 pub struct Disassembler {}
 
 impl Disassembler {
-    pub fn disassemble_chunk(chunk: &Chunk, name: String) {
-        println!("== {} ==", name);
+    pub fn disassemble_chunk(out: &mut dyn Write, chunk: &Chunk, heap: &Heap, name: String) -> io::Result<()> {
+        writeln!(out, "== {} ==", name)?;
 
         let mut offset: usize = 0;
         while offset < chunk.code.len() {
-            offset = Disassembler::disassemble_instruction(&chunk, offset);
+            offset = Disassembler::disassemble_instruction(out, chunk, heap, offset)?;
         }
+        Ok(())
     }
 
-    pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-        print!("{} ", offset);
+    pub fn disassemble_instruction(out: &mut dyn Write, chunk: &Chunk, heap: &Heap, offset: usize) -> io::Result<usize> {
+        write!(out, "{} ", offset)?;
 
-        if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
-            print!("  | ")
+        let line = chunk.line_at(offset);
+        if offset > 0 && line == chunk.line_at(offset - 1) {
+            write!(out, "  | ")?;
         } else {
-            print!("{} ", chunk.lines[offset]);
+            write!(out, "{} ", line)?;
         }
 
-        let instruction: OpCode = unsafe { std::mem::transmute(chunk.code[offset]) };
-        // TODO replace this later with a safe solution, right now it assumes that it is always going to be of Type Enum
+        let instruction = match OpCode::try_from(chunk.code[offset]) {
+            Ok(instruction) => instruction,
+            Err(byte) => {
+                writeln!(out, "Unknown opcode {}", byte)?;
+                return Ok(offset + 1);
+            }
+        };
         match instruction {
-            OpCode::OpReturn => return Disassembler::simple_instruction("OpReturn", offset),
-            OpCode::OpNegate => return Disassembler::simple_instruction("OpNegate", offset),
-            OpCode::OpAdd => return Disassembler::simple_instruction("OpAdd", offset),
-            OpCode::OpSub => return Disassembler::simple_instruction("OpSub", offset),
-            OpCode::OpMult => return Disassembler::simple_instruction("OpMult", offset),
-            OpCode::OpDiv => return Disassembler::simple_instruction("OpDiv", offset),
-            OpCode::OpMod => return Disassembler::simple_instruction("OpMod",offset),
+            OpCode::OpReturn => Disassembler::simple_instruction(out, "OpReturn", offset),
+            OpCode::OpNegate => Disassembler::simple_instruction(out, "OpNegate", offset),
+            OpCode::OpAdd => Disassembler::simple_instruction(out, "OpAdd", offset),
+            OpCode::OpSub => Disassembler::simple_instruction(out, "OpSub", offset),
+            OpCode::OpMult => Disassembler::simple_instruction(out, "OpMult", offset),
+            OpCode::OpDiv => Disassembler::simple_instruction(out, "OpDiv", offset),
+            OpCode::OpMod => Disassembler::simple_instruction(out, "OpMod",offset),
+            OpCode::OpNil => Disassembler::simple_instruction(out, "OpNil", offset),
+            OpCode::OpTrue => Disassembler::simple_instruction(out, "OpTrue", offset),
+            OpCode::OpFalse => Disassembler::simple_instruction(out, "OpFalse", offset),
+            OpCode::OpEqual => Disassembler::simple_instruction(out, "OpEqual", offset),
+            OpCode::OpGreater => Disassembler::simple_instruction(out, "OpGreater", offset),
+            OpCode::OpLess => Disassembler::simple_instruction(out, "OpLess", offset),
+            OpCode::OpNot => Disassembler::simple_instruction(out, "OpNot", offset),
+            OpCode::OpPrint => Disassembler::simple_instruction(out, "OpPrint", offset),
+            OpCode::OpPop => Disassembler::simple_instruction(out, "OpPop", offset),
             OpCode::OpConstant => {
-                return Disassembler::constant_instruction(&chunk, "OpConstant", offset)
+                Disassembler::constant_instruction(out, chunk, heap, "OpConstant", offset)
+            },
+            OpCode::OpConstantLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpConstantLong", offset)
+            },
+            OpCode::OpDefineGlobal => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpDefineGlobal", offset)
+            },
+            OpCode::OpDefineGlobalLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpDefineGlobalLong", offset)
+            },
+            OpCode::OpGetGlobal => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpGetGlobal", offset)
+            },
+            OpCode::OpGetGlobalLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpGetGlobalLong", offset)
+            },
+            OpCode::OpSetGlobal => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpSetGlobal", offset)
+            },
+            OpCode::OpSetGlobalLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpSetGlobalLong", offset)
+            },
+            OpCode::OpJump => Disassembler::jump_instruction(out, chunk, "OpJump", 1, offset),
+            OpCode::OpJumpIfFalse => Disassembler::jump_instruction(out, chunk, "OpJumpIfFalse", 1, offset),
+            OpCode::OpLoop => Disassembler::jump_instruction(out, chunk, "OpLoop", -1, offset),
+            OpCode::OpCall => Disassembler::byte_instruction(out, chunk, "OpCall", offset),
+            OpCode::OpClosure => Disassembler::closure_instruction(out, chunk, heap, "OpClosure", offset),
+            OpCode::OpClosureLong => Disassembler::closure_instruction(out, chunk, heap, "OpClosureLong", offset),
+            OpCode::OpGetUpvalue => Disassembler::byte_instruction(out, chunk, "OpGetUpvalue", offset),
+            OpCode::OpSetUpvalue => Disassembler::byte_instruction(out, chunk, "OpSetUpvalue", offset),
+            OpCode::OpCloseUpvalue => Disassembler::simple_instruction(out, "OpCloseUpvalue", offset),
+            OpCode::OpClass => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpClass", offset)
+            },
+            OpCode::OpClassLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpClassLong", offset)
+            },
+            OpCode::OpGetProperty => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpGetProperty", offset)
+            },
+            OpCode::OpGetPropertyLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpGetPropertyLong", offset)
+            },
+            OpCode::OpSetProperty => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpSetProperty", offset)
+            },
+            OpCode::OpSetPropertyLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpSetPropertyLong", offset)
+            },
+            OpCode::OpMethod => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpMethod", offset)
+            },
+            OpCode::OpMethodLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpMethodLong", offset)
+            },
+            OpCode::OpInvoke => Disassembler::invoke_instruction(out, chunk, heap, "OpInvoke", offset),
+            OpCode::OpInvokeLong => Disassembler::invoke_instruction(out, chunk, heap, "OpInvokeLong", offset),
+            OpCode::OpInherit => Disassembler::simple_instruction(out, "OpInherit", offset),
+            OpCode::OpGetSuper => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpGetSuper", offset)
+            },
+            OpCode::OpGetSuperLong => {
+                Disassembler::constant_instruction(out, chunk, heap, "OpGetSuperLong", offset)
             },
-            // _ => {
-            //     println!("Unknown opcode {}", instruction as u8);
-            //     return offset + 1;
-            // }
+            OpCode::OpSuperInvoke => Disassembler::invoke_instruction(out, chunk, heap, "OpSuperInvoke", offset),
+            OpCode::OpSuperInvokeLong => Disassembler::invoke_instruction(out, chunk, heap, "OpSuperInvokeLong", offset),
+            OpCode::OpGetLocal => Disassembler::byte_instruction(out, chunk, "OpGetLocal", offset),
+            OpCode::OpSetLocal => Disassembler::byte_instruction(out, chunk, "OpSetLocal", offset),
         }
     }
 
-    pub fn constant_instruction(chunk: &Chunk, name: &str, offset: usize) -> usize {
-        let constant: u8 = chunk.code[offset + 1];
-        print!("{} {} ", name, constant);
-        println!("'{}'", chunk.constants[constant as usize]);
+    pub fn constant_instruction(out: &mut dyn Write, chunk: &Chunk, heap: &Heap, name: &str, offset: usize) -> io::Result<usize> {
+        let (constant, next) = Disassembler::constant_operand(chunk, offset);
+        write!(out, "{} {} ", name, constant)?;
+        writeln!(out, "'{}'", chunk.constants[constant].display(heap))?;
+
+        Ok(next)
+    }
+
+    pub fn invoke_instruction(out: &mut dyn Write, chunk: &Chunk, heap: &Heap, name: &str, offset: usize) -> io::Result<usize> {
+        let (constant, next) = Disassembler::constant_operand(chunk, offset);
+        let arg_count: u8 = chunk.code[next];
+        write!(out, "{} ({} args) {} ", name, arg_count, constant)?;
+        writeln!(out, "'{}'", chunk.constants[constant].display(heap))?;
+
+        Ok(next + 1)
+    }
+
+    /// prints the function constant followed by one line per captured variable
+    pub fn closure_instruction(out: &mut dyn Write, chunk: &Chunk, heap: &Heap, name: &str, offset: usize) -> io::Result<usize> {
+        let (constant, next) = Disassembler::constant_operand(chunk, offset);
+        let function = chunk.constants[constant];
+        writeln!(out, "{} {} '{}'", name, constant, function.display(heap))?;
+
+        let upvalue_count = match function.value {
+            ValueType::Obj(reference) => heap.as_function(reference).upvalue_count,
+            _ => 0,
+        };
+        let mut offset = next;
+        for _ in 0..upvalue_count {
+            let is_local = chunk.code[offset];
+            let index = chunk.code[offset + 1];
+            let kind = if is_local == 1 { "local" } else { "upvalue" };
+            writeln!(out, "{}   | {} {}", offset, kind, index)?;
+            offset += 2;
+        }
+
+        Ok(offset)
+    }
+
+    /// the constant index of the instruction at `offset` and where the bytes after it start,
+    /// long forms carry a three byte index and every other constant instruction a single byte
+    fn constant_operand(chunk: &Chunk, offset: usize) -> (usize, usize) {
+        if OpCode::try_from(chunk.code[offset]).is_ok_and(OpCode::is_long) {
+            let index = (chunk.code[offset + 1] as usize) << 16
+                | (chunk.code[offset + 2] as usize) << 8
+                | chunk.code[offset + 3] as usize;
+            (index, offset + 4)
+        } else {
+            (chunk.code[offset + 1] as usize, offset + 2)
+        }
+    }
+
+    pub fn byte_instruction(out: &mut dyn Write, chunk: &Chunk, name: &str, offset: usize) -> io::Result<usize> {
+        let slot: u8 = chunk.code[offset + 1];
+        writeln!(out, "{} {}", name, slot)?;
+
+        Ok(offset + 2)
+    }
+
+    /// prints the absolute offset a jump lands on, `sign` is -1 for backward jumps
+    pub fn jump_instruction(out: &mut dyn Write, chunk: &Chunk, name: &str, sign: i64, offset: usize) -> io::Result<usize> {
+        let jump = ((chunk.code[offset + 1] as u16) << 8) | chunk.code[offset + 2] as u16;
+        let target = offset as i64 + 3 + sign * jump as i64;
+        writeln!(out, "{} {} -> {}", name, offset, target)?;
 
-        return offset + 2;
+        Ok(offset + 3)
     }
 
-    pub fn simple_instruction(opcode: &str, offset: usize) -> usize {
-        println!("{}", opcode);
-        return offset + 1;
+    pub fn simple_instruction(out: &mut dyn Write, opcode: &str, offset: usize) -> io::Result<usize> {
+        writeln!(out, "{}", opcode)?;
+        Ok(offset + 1)
     }
 }
@@ -0,0 +1,221 @@
+//! The handle a host program uses to compile and run scripts.
+
+use crate::bytecode::{self, BytecodeError};
+use crate::convert::{FromValue, IntoValue};
+use crate::diagnostic::Diagnostic;
+use crate::heap::{Heap, Root};
+use crate::object::{NativeFn, ObjRef};
+use crate::value::{Value, ValueType};
+use crate::vm::{InterpretResult, RuntimeError, Settings, VM};
+
+/// A compiled script, it can only be run by the `Vm` that compiled it.
+/// the script stays in the vm's heap until the last clone of it is dropped
+#[derive(Debug, Clone)]
+pub struct Function {
+    root: Root,
+}
+
+impl Function {
+    /// the script, as long as `heap` is the one that compiled it
+    fn reference(&self, heap: &Heap) -> Option<ObjRef> {
+        if heap.holds(&self.root) {
+            Some(self.root.reference())
+        } else {
+            None
+        }
+    }
+}
+
+/// A script value held by the host program, the collector won't free it while the handle
+/// or one of its clones is alive. `Vm::value` makes one, and `call` and `get` return one
+/// when asked for a `Handle`
+#[derive(Debug, Clone)]
+pub struct Handle {
+    value: Value,
+    // numbers, bools and nil have nothing to keep alive
+    root: Option<Root>,
+}
+
+impl Handle {
+    pub(crate) fn new(value: Value, heap: &Heap) -> Self {
+        let root = match value.value {
+            ValueType::Obj(reference) => Some(heap.root(reference)),
+            _ => None,
+        };
+        Self { value, root }
+    }
+
+    /// the value, as long as it lives in `heap` rather than another vm's
+    pub(crate) fn value(&self, heap: &Heap) -> Result<Value, RuntimeError> {
+        match &self.root {
+            Some(root) if !heap.holds(root) => Err(RuntimeError::new("The value belongs to another vm.")),
+            _ => Ok(self.value),
+        }
+    }
+}
+
+/// A long-lived interpreter, globals defined by one script stay visible to the next.
+///
+/// ```
+/// let mut vm = rustmox::Vm::new();
+/// let script = vm.compile("func double(x) { return x * 2; }").unwrap();
+/// vm.run(&script).unwrap();
+///
+/// let four = vm.value(4.0).unwrap();
+/// let result: f64 = vm.call("double", &[four]).unwrap();
+/// assert_eq!(result, 8.0);
+///
+/// // a `Handle` keeps a script value alive between calls
+/// let list = vm.value(vec!["a", "b"]).unwrap();
+/// vm.set("letters", &list).unwrap();
+/// let letters: Vec<String> = vm.get("letters").unwrap();
+/// assert_eq!(letters, ["a", "b"]);
+/// ```
+pub struct Vm {
+    vm: VM,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm::with_settings(Settings::default())
+    }
+
+    pub fn with_settings(settings: Settings) -> Self {
+        Self {
+            vm: VM::with_settings(Heap::new(), settings),
+        }
+    }
+
+    /// compiles `source` without running it
+    pub fn compile(&mut self, source: &str) -> Result<Function, Vec<Diagnostic>> {
+        let reference = self.vm.compile(source.to_string())?;
+        // the caller may run other code before this, which could collect it
+        Ok(Function { root: self.vm.heap().root(reference) })
+    }
+
+    /// the `.moxc` bytes of a compiled script, see `bytecode`
+    pub fn serialize(&self, function: &Function) -> Result<Vec<u8>, BytecodeError> {
+        let reference = function.reference(self.vm.heap()).ok_or(BytecodeError::OtherVm)?;
+        bytecode::serialize(reference, self.vm.heap())
+    }
+
+    /// loads a script serialized by `serialize`, possibly by another process
+    pub fn load(&mut self, bytes: &[u8]) -> Result<Function, BytecodeError> {
+        let reference = bytecode::deserialize(bytes, self.vm.heap_mut())?;
+        Ok(Function { root: self.vm.heap().root(reference) })
+    }
+
+    pub fn run(&mut self, function: &Function) -> Result<(), RuntimeError> {
+        let reference = function
+            .reference(self.vm.heap())
+            .ok_or_else(|| RuntimeError::new("The script was compiled by another vm."))?;
+        match self.vm.interpret(reference) {
+            InterpretResult::InterpretRuntimeError(error) => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// compiles and runs `source` in one go
+    pub fn interpret(&mut self, source: &str) -> InterpretResult {
+        // nothing runs between compiling and interpreting, so there is no need to root it
+        match self.vm.compile(source.to_string()) {
+            Ok(function) => self.vm.interpret(function),
+            Err(diagnostics) => InterpretResult::InterpretCompileError(diagnostics),
+        }
+    }
+
+    /// calls the global function, class or native called `name`
+    pub fn call<R: FromValue>(&mut self, name: &str, args: &[Handle]) -> Result<R, RuntimeError> {
+        let callee = self.global_value(name)?;
+        let args = args.iter().map(|arg| arg.value(self.vm.heap())).collect::<Result<Vec<_>, _>>()?;
+        let result = self.vm.call_function(callee, &args)?;
+        R::from_value(result, self.vm.heap())
+    }
+
+    /// converts a Rust value into a script value, for example to pass it to `call`
+    pub fn value<T: IntoValue>(&mut self, value: T) -> Result<Handle, RuntimeError> {
+        let value = value.into_value(self.vm.heap_mut())?;
+        Ok(Handle::new(value, self.vm.heap()))
+    }
+
+    /// reads the global `name`, converting it to `T`
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, RuntimeError> {
+        let value = self.global_value(name)?;
+        T::from_value(value, self.vm.heap())
+    }
+
+    /// defines or overwrites the global `name`
+    pub fn set<T: IntoValue>(&mut self, name: &str, value: T) -> Result<(), RuntimeError> {
+        let value = value.into_value(self.vm.heap_mut())?;
+        self.vm.set_global(name, value);
+        Ok(())
+    }
+
+    /// what scripts printed since the last call, `None` unless the settings capture output
+    pub fn captured_output(&self) -> Option<String> {
+        self.vm.captured_output()
+    }
+
+    /// exposes a Rust function to scripts as a global called `name`
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        self.vm.define_native(name, arity, function);
+    }
+
+    fn global_value(&self, name: &str) -> Result<Value, RuntimeError> {
+        self.vm
+            .global(name)
+            .ok_or_else(|| RuntimeError::new(&format!("Undefined variable '{}'.", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_keep_their_values_alive() {
+        let mut vm = Vm::with_settings(Settings::builder().stress_gc(true).capture_output().build());
+        let script = vm.compile("func greet(name) { return \"hello \" + name; }").unwrap();
+        let name = vm.value(vec!["m", "o", "x"]).unwrap();
+        vm.run(&script).unwrap();
+        let mox = vm.value("mox").unwrap();
+        let greeting: Handle = vm.call("greet", &[mox]).unwrap();
+
+        // every one of these allocations collects, only the handles keep their values around
+        for _ in 0..10 {
+            assert!(matches!(vm.interpret("dec junk = \"ju\" + \"nk\";"), InterpretResult::InterpretOk));
+        }
+
+        vm.set("greeting", &greeting).unwrap();
+        vm.set("name", &name).unwrap();
+        assert!(matches!(vm.interpret("print greeting + \"!\";"), InterpretResult::InterpretOk));
+        assert_eq!(vm.captured_output().unwrap(), "hello mox!\n");
+        assert_eq!(vm.get::<Vec<String>>("name").unwrap(), ["m", "o", "x"]);
+    }
+
+    #[test]
+    fn another_vms_scripts_and_values_are_refused() {
+        let mut a = Vm::new();
+        let mut b = Vm::with_settings(Settings::builder().capture_output().build());
+        let script = a.compile("print \"from a\";").unwrap();
+        let name = a.value("a").unwrap();
+        let names = vec![a.value("a").unwrap()];
+        b.interpret("func echo(value) { return value; }");
+
+        assert!(b.run(&script).is_err());
+        assert!(matches!(b.serialize(&script), Err(BytecodeError::OtherVm)));
+        assert!(b.set("name", &name).is_err());
+        assert!(b.value(names).is_err());
+        assert!(b.call::<Handle>("echo", &[name]).is_err());
+        // numbers carry no heap object, so any vm can take them
+        let two = a.value(2.0).unwrap();
+        assert_eq!(b.call::<f64>("echo", &[two]).unwrap(), 2.0);
+        assert_eq!(b.captured_output().unwrap(), "");
+    }
+}
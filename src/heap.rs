@@ -0,0 +1,417 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::chunk::LineRun;
+use crate::object::{Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjList, ObjMap, ObjNative, ObjRef, ObjString, ObjUpvalue};
+use crate::value::{Value, ValueType};
+
+pub const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+pub const GC_HEAP_GROW_FACTOR: usize = 2;
+
+/// how many `Root`s the host program holds for each object
+type Held = Rc<RefCell<HashMap<ObjRef, usize>>>;
+
+/// Owns every object created by the compiler and the vm.
+/// Strings are interned, so two equal strings always share one `ObjRef`.
+///
+/// The heap never decides on its own when to collect, the vm asks
+/// `should_collect` before allocating and drives `mark_*`, `trace_references` and `sweep`.
+pub struct Heap {
+    objects: Vec<Option<Obj>>,
+    marks: Vec<bool>,
+    // what each slot was charged when it was allocated, objects can grow afterwards
+    // so sweeping gives back exactly this rather than their current size
+    sizes: Vec<usize>,
+    free_slots: Vec<usize>,
+    strings: HashMap<String, ObjRef>,
+    // marked objects whose references have not been traced yet
+    gray_stack: Vec<ObjRef>,
+    bytes_allocated: usize,
+    pub next_gc: usize,
+    pub grow_factor: usize,
+    held: Held,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            sizes: Vec::new(),
+            free_slots: Vec::new(),
+            strings: HashMap::new(),
+            gray_stack: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+            grow_factor: GC_HEAP_GROW_FACTOR,
+            held: Held::default(),
+        }
+    }
+
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        let size = size_of(&obj);
+        self.bytes_allocated += size;
+        match self.free_slots.pop() {
+            Some(slot) => {
+                self.objects[slot] = Some(obj);
+                self.marks[slot] = false;
+                self.sizes[slot] = size;
+                ObjRef(slot)
+            }
+            None => {
+                self.objects.push(Some(obj));
+                self.marks.push(false);
+                self.sizes.push(size);
+                ObjRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    /// number of live objects, freed slots are not counted
+    pub fn object_count(&self) -> usize {
+        self.objects.len() - self.free_slots.len()
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.bytes_allocated > self.next_gc
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let ValueType::Obj(reference) = value.value {
+            self.mark_object(reference);
+        }
+    }
+
+    pub fn mark_object(&mut self, reference: ObjRef) {
+        if self.marks[reference.0] {
+            return;
+        }
+        self.marks[reference.0] = true;
+        self.gray_stack.push(reference);
+    }
+
+    /// keeps `reference` alive until the returned `Root` and every clone of it are dropped
+    pub fn root(&self, reference: ObjRef) -> Root {
+        *self.held.borrow_mut().entry(reference).or_insert(0) += 1;
+        Root {
+            reference,
+            held: Rc::downgrade(&self.held),
+        }
+    }
+
+    /// whether `root` came from this heap, a root from another heap names some other object
+    pub fn holds(&self, root: &Root) -> bool {
+        Weak::ptr_eq(&root.held, &Rc::downgrade(&self.held))
+    }
+
+    /// marks every object the host program holds a `Root` for
+    pub fn mark_held(&mut self) {
+        let held: Vec<ObjRef> = self.held.borrow().keys().copied().collect();
+        for reference in held {
+            self.mark_object(reference);
+        }
+    }
+
+    /// blackens gray objects until every object reachable from the roots is marked
+    pub fn trace_references(&mut self) {
+        while let Some(reference) = self.gray_stack.pop() {
+            self.blacken_object(reference);
+        }
+    }
+
+    fn blacken_object(&mut self, reference: ObjRef) {
+        let mut children: Vec<Value> = Vec::new();
+        match self.get(reference) {
+            Obj::String(_) => {}
+            Obj::Function(function) => {
+                if let Some(name) = function.name {
+                    children.push(Value::from_obj(name));
+                }
+                children.extend(function.chunk.constants.iter().copied());
+            }
+            Obj::Closure(closure) => {
+                children.push(Value::from_obj(closure.function));
+                children.extend(closure.upvalues.iter().map(|&upvalue| Value::from_obj(upvalue)));
+            }
+            Obj::Upvalue(upvalue) => {
+                if let Some(closed) = upvalue.closed {
+                    children.push(closed);
+                }
+            }
+            Obj::Class(class) => {
+                children.push(Value::from_obj(class.name));
+                for (&name, &method) in class.methods.iter() {
+                    children.push(Value::from_obj(name));
+                    children.push(Value::from_obj(method));
+                }
+            }
+            Obj::BoundMethod(bound) => {
+                children.push(bound.receiver);
+                children.push(Value::from_obj(bound.method));
+            }
+            Obj::Native(native) => children.push(Value::from_obj(native.name)),
+            Obj::Instance(instance) => {
+                children.push(Value::from_obj(instance.class));
+                for (&name, &value) in instance.fields.iter() {
+                    children.push(Value::from_obj(name));
+                    children.push(value);
+                }
+            }
+            Obj::List(list) => children.extend(list.items.iter().copied()),
+            Obj::Map(map) => {
+                for (&key, &value) in map.entries.iter() {
+                    children.push(Value::from_obj(key));
+                    children.push(value);
+                }
+            }
+        }
+
+        for child in children {
+            self.mark_value(child);
+        }
+    }
+
+    /// frees every unmarked object and clears the marks for the next cycle.
+    /// the intern table holds its strings weakly, so unmarked strings are dropped from it first
+    pub fn sweep(&mut self) {
+        let marks = &self.marks;
+        self.strings.retain(|_, reference| marks[reference.0]);
+
+        for slot in 0..self.objects.len() {
+            if self.marks[slot] {
+                self.marks[slot] = false;
+                continue;
+            }
+            if self.objects[slot].take().is_some() {
+                self.bytes_allocated -= self.sizes[slot];
+                self.free_slots.push(slot);
+            }
+        }
+
+        self.next_gc = self.bytes_allocated * self.grow_factor;
+    }
+
+    /// the interned string for `chars`, if there is one, without allocating
+    pub fn find_string(&self, chars: &str) -> Option<ObjRef> {
+        self.strings.get(chars).copied()
+    }
+
+    /// interns a copy of `chars`
+    pub fn copy_string(&mut self, chars: &str) -> ObjRef {
+        if let Some(&interned) = self.strings.get(chars) {
+            return interned;
+        }
+        self.take_string(chars.to_string())
+    }
+
+    /// interns `chars`, taking ownership of it
+    pub fn take_string(&mut self, chars: String) -> ObjRef {
+        if let Some(&interned) = self.strings.get(&chars) {
+            return interned;
+        }
+        let reference = self.alloc(Obj::String(ObjString::new(chars.clone())));
+        self.strings.insert(chars, reference);
+        reference
+    }
+
+    pub fn get(&self, reference: ObjRef) -> &Obj {
+        match &self.objects[reference.0] {
+            Some(obj) => obj,
+            None => panic!("use of freed object {:?}", reference),
+        }
+    }
+
+    pub fn get_mut(&mut self, reference: ObjRef) -> &mut Obj {
+        match &mut self.objects[reference.0] {
+            Some(obj) => obj,
+            None => panic!("use of freed object {:?}", reference),
+        }
+    }
+
+    pub fn as_string(&self, reference: ObjRef) -> &ObjString {
+        match self.get(reference) {
+            Obj::String(string) => string,
+            obj => panic!("expected a string, found {:?}", obj),
+        }
+    }
+
+    pub fn as_function(&self, reference: ObjRef) -> &ObjFunction {
+        match self.get(reference) {
+            Obj::Function(function) => function,
+            obj => panic!("expected a function, found {:?}", obj),
+        }
+    }
+
+    pub fn as_closure(&self, reference: ObjRef) -> &ObjClosure {
+        match self.get(reference) {
+            Obj::Closure(closure) => closure,
+            obj => panic!("expected a closure, found {:?}", obj),
+        }
+    }
+
+    pub fn as_class(&self, reference: ObjRef) -> &ObjClass {
+        match self.get(reference) {
+            Obj::Class(class) => class,
+            obj => panic!("expected a class, found {:?}", obj),
+        }
+    }
+
+    pub fn as_class_mut(&mut self, reference: ObjRef) -> &mut ObjClass {
+        match self.get_mut(reference) {
+            Obj::Class(class) => class,
+            obj => panic!("expected a class, found {:?}", obj),
+        }
+    }
+
+    pub fn as_bound_method(&self, reference: ObjRef) -> &ObjBoundMethod {
+        match self.get(reference) {
+            Obj::BoundMethod(bound) => bound,
+            obj => panic!("expected a bound method, found {:?}", obj),
+        }
+    }
+
+    pub fn as_instance(&self, reference: ObjRef) -> &ObjInstance {
+        match self.get(reference) {
+            Obj::Instance(instance) => instance,
+            obj => panic!("expected an instance, found {:?}", obj),
+        }
+    }
+
+    pub fn as_instance_mut(&mut self, reference: ObjRef) -> &mut ObjInstance {
+        match self.get_mut(reference) {
+            Obj::Instance(instance) => instance,
+            obj => panic!("expected an instance, found {:?}", obj),
+        }
+    }
+
+    pub fn as_native(&self, reference: ObjRef) -> &ObjNative {
+        match self.get(reference) {
+            Obj::Native(native) => native,
+            obj => panic!("expected a native function, found {:?}", obj),
+        }
+    }
+
+    pub fn as_upvalue(&self, reference: ObjRef) -> &ObjUpvalue {
+        match self.get(reference) {
+            Obj::Upvalue(upvalue) => upvalue,
+            obj => panic!("expected an upvalue, found {:?}", obj),
+        }
+    }
+
+    pub fn as_upvalue_mut(&mut self, reference: ObjRef) -> &mut ObjUpvalue {
+        match self.get_mut(reference) {
+            Obj::Upvalue(upvalue) => upvalue,
+            obj => panic!("expected an upvalue, found {:?}", obj),
+        }
+    }
+
+    pub fn as_list(&self, reference: ObjRef) -> &ObjList {
+        match self.get(reference) {
+            Obj::List(list) => list,
+            obj => panic!("expected a list, found {:?}", obj),
+        }
+    }
+
+    pub fn as_map(&self, reference: ObjRef) -> &ObjMap {
+        match self.get(reference) {
+            Obj::Map(map) => map,
+            obj => panic!("expected a map, found {:?}", obj),
+        }
+    }
+}
+
+/// An object the host program holds on to, the collector treats it as a root until
+/// this and every clone of it are dropped
+#[derive(Debug)]
+pub struct Root {
+    reference: ObjRef,
+    // weak so a root that outlives its heap doesn't keep the bookkeeping around
+    held: Weak<RefCell<HashMap<ObjRef, usize>>>,
+}
+
+impl Root {
+    pub fn reference(&self) -> ObjRef {
+        self.reference
+    }
+}
+
+impl Clone for Root {
+    fn clone(&self) -> Self {
+        if let Some(held) = self.held.upgrade() {
+            *held.borrow_mut().entry(self.reference).or_insert(0) += 1;
+        }
+        Self {
+            reference: self.reference,
+            held: self.held.clone(),
+        }
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        if let Some(held) = self.held.upgrade() {
+            let mut held = held.borrow_mut();
+            if let Some(count) = held.get_mut(&self.reference) {
+                *count -= 1;
+                if *count == 0 {
+                    held.remove(&self.reference);
+                }
+            }
+        }
+    }
+}
+
+/// a rough count of the bytes an object keeps alive, used to pace collections
+fn size_of(obj: &Obj) -> usize {
+    let payload = match obj {
+        Obj::String(string) => string.chars.capacity(),
+        Obj::Function(function) => {
+            function.chunk.code.capacity()
+                + function.chunk.lines.capacity() * mem::size_of::<LineRun>()
+                + function.chunk.constants.capacity() * mem::size_of::<Value>()
+        }
+        Obj::Closure(closure) => closure.upvalues.capacity() * mem::size_of::<ObjRef>(),
+        Obj::Upvalue(_) => 0,
+        Obj::Class(class) => class.methods.capacity() * mem::size_of::<(ObjRef, ObjRef)>(),
+        Obj::BoundMethod(_) => 0,
+        Obj::Native(_) => 0,
+        Obj::Instance(instance) => instance.fields.capacity() * mem::size_of::<(ObjRef, Value)>(),
+        Obj::List(list) => list.items.capacity() * mem::size_of::<Value>(),
+        Obj::Map(map) => map.entries.capacity() * mem::size_of::<(ObjRef, Value)>(),
+    };
+    mem::size_of::<Obj>() + payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweeping_gives_back_what_allocating_charged() {
+        let mut heap = Heap::new();
+        let kept = heap.alloc(Obj::List(ObjList::new(Vec::new())));
+        let charged = heap.bytes_allocated();
+        let grown = heap.alloc(Obj::List(ObjList::new(Vec::new())));
+
+        // grows after it was charged, like an instance gaining fields
+        if let Obj::List(list) = heap.get_mut(grown) {
+            list.items.extend((0..100).map(|_| Value::new()));
+        }
+        heap.mark_object(kept);
+        heap.sweep();
+
+        assert_eq!(heap.bytes_allocated(), charged);
+    }
+}
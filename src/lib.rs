@@ -0,0 +1,49 @@
+//! rustmox, a bytecode virtual machine for the mox language.
+//!
+//! Scripts are compiled and run through a [`Vm`], which keeps its globals between runs:
+//!
+//! ```
+//! let mut vm = rustmox::Vm::new();
+//! vm.set("name", "mox").unwrap();
+//! vm.interpret("dec greeting = \"hello \" + name;");
+//! let greeting: String = vm.get("greeting").unwrap();
+//! assert_eq!(greeting, "hello mox");
+//! ```
+
+pub mod chunk;
+pub mod disassembler;
+pub mod opcode;
+pub mod scanner;
+pub mod token;
+pub mod vm;
+pub mod compiler;
+pub mod parser;
+pub mod value;
+pub mod object;
+pub mod heap;
+pub mod diagnostic;
+pub mod native;
+pub mod convert;
+pub mod embed;
+pub mod bytecode;
+
+pub use crate::bytecode::BytecodeError;
+pub use crate::convert::{FromValue, IntoValue};
+pub use crate::diagnostic::Diagnostic;
+pub use crate::embed::{Function, Handle, Vm};
+pub use crate::value::Value;
+pub use crate::vm::{InterpretResult, RuntimeError, Settings, SettingsBuilder};
+
+use crate::heap::Heap;
+use crate::vm::VM;
+
+/// compiles and runs `source` on a fresh vm
+pub fn interpret(source: String) -> InterpretResult {
+    let mut vm = VM::new(Heap::new());
+    let function = match vm.compile(source) {
+        Ok(function) => function,
+        Err(diagnostics) => return InterpretResult::InterpretCompileError(diagnostics),
+    };
+
+    vm.interpret(function)
+}
@@ -1,34 +1,124 @@
-pub mod chunk;
-pub mod disassembler;
-pub mod opcode;
-pub mod scanner;
-pub mod token;
-pub mod vm;
-pub mod compiler;
-pub mod parser;
-pub mod value;
+mod repl;
 // use crate::chunk::Chunk;
 
 // use crate::opcode::OpCode;
 // use crate::vm::VM;
 
 use std::env;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
 
-use crate::vm::{VM,InterpretResult};
-use crate::chunk::Chunk;
-use  crate::compiler::Compiler;
+use rustmox::bytecode;
+use rustmox::{InterpretResult, RuntimeError, Settings, Vm};
+use crate::repl::Repl;
 
-fn main() -> std::io::Result<()> {
+const USAGE: &str = "usage: rustmox [--trace] [--dump-bytecode] [script]
+       rustmox compile [--dump-bytecode] <script> [-o <output>]
+       rustmox run [--trace] <script or compiled script>";
 
-    let args: Vec<String> = env::args().collect();
+/// what to do with the script named on the command line
+enum Command {
+    /// run a source file, or start the repl without one
+    Interpret,
+    /// write a compiled `.moxc` file
+    Compile,
+    /// run either a source file or a compiled one
+    Run,
+}
+
+fn main() -> io::Result<()> {
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, args) = match args.first().map(String::as_str) {
+        Some("compile") => (Command::Compile, &args[1..]),
+        Some("run") => (Command::Run, &args[1..]),
+        _ => (Command::Interpret, &args[..]),
+    };
+
+    let mut settings = Settings::builder();
+    let mut path = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => settings = settings.trace_execution(true),
+            "--dump-bytecode" => settings = settings.print_code(true),
+            "-o" if matches!(command, Command::Compile) => match args.next() {
+                Some(arg) => output = Some(arg),
+                None => usage(),
+            },
+            flag if flag.starts_with('-') => {
+                eprintln!("Unknown flag {}", flag);
+                usage();
+            }
+            _ if path.is_some() => usage(),
+            _ => path = Some(arg),
+        }
+    }
+    let settings = settings.build();
+
+    match (command, path) {
+        (Command::Interpret, None) => Repl::new(settings).run(),
+        (_, None) => usage(),
+        (Command::Compile, Some(path)) => compile(settings, path, output),
+        (Command::Interpret, Some(path)) | (Command::Run, Some(path)) => run(settings, path),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(64);
+}
+
+/// compiles `path` and writes the bytecode next to it, or to `output`
+fn compile(settings: Settings, path: &str, output: Option<&String>) -> io::Result<()> {
+    let source = fs::read_to_string(path)?;
+    let mut vm = Vm::with_settings(settings);
+    let function = match vm.compile(&source) {
+        Ok(function) => function,
+        Err(diagnostics) => {
+            for diagnostic in diagnostics.iter() {
+                eprint!("{}", diagnostic.render(path, &source));
+            }
+            process::exit(65);
+        }
+    };
+
+    let bytes = match vm.serialize(&function) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            process::exit(65);
+        }
+    };
+    match output {
+        Some(output) => fs::write(output, bytes),
+        None => fs::write(Path::new(path).with_extension("moxc"), bytes),
+    }
+}
+
+/// runs a script, compiled scripts are recognised by their header
+fn run(settings: Settings, path: &str) -> io::Result<()> {
+    let contents = fs::read(path)?;
+    let mut vm = Vm::with_settings(settings);
 
-    let mut file = File::open(&args[1])?;
-    let mut contents = String::new();
+    if bytecode::is_bytecode(&contents) {
+        let function = match vm.load(&contents) {
+            Ok(function) => function,
+            Err(error) => {
+                eprintln!("{}: {}", path, error);
+                process::exit(65);
+            }
+        };
+        if let Err(error) = vm.run(&function) {
+            runtime_error(error);
+        }
+        return Ok(());
+    }
 
-    file.read_to_string(&mut contents)?;
-    contents.push('\0');
+    let source = String::from_utf8(contents).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
     // let mut scanner = Scanner::new(&contents);
     // loop {
     //     let token = scanner.scan_token();
@@ -39,23 +129,21 @@ fn main() -> std::io::Result<()> {
     //     }
     // }
 
-    interpret(contents);
+    match vm.interpret(&source) {
+        InterpretResult::InterpretCompileError(diagnostics) => {
+            for diagnostic in diagnostics.iter() {
+                eprint!("{}", diagnostic.render(path, &source));
+            }
+            process::exit(65);
+        }
+        InterpretResult::InterpretRuntimeError(error) => runtime_error(error),
+        InterpretResult::InterpretOk => {}
+    }
 
     Ok(())
 }
 
-
-
-pub fn interpret(source: String) -> InterpretResult {
-    let mut chunk = Chunk::new();
-    let mut compiler = Compiler::from_source(source, &mut chunk);
-    let compiled = compiler.compile();
-    if !compiled {
-        return InterpretResult::InterpretCompileError
-    }
-
-
-    let mut vm = VM::new(chunk);
-
-    vm.interpret()
-}
\ No newline at end of file
+fn runtime_error(error: RuntimeError) -> ! {
+    eprintln!("{}", error);
+    process::exit(70);
+}
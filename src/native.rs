@@ -0,0 +1,15 @@
+//! Built-in functions every vm starts with.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::value::Value;
+use crate::vm::{RuntimeError, VM};
+
+/// seconds since the unix epoch, handy for timing scripts
+pub fn clock(_vm: &mut VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => Ok(Value::from_float(elapsed.as_secs_f64())),
+        Err(_) => Err(RuntimeError::new("System clock is set before the unix epoch.")),
+    }
+}
+
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::chunk::Chunk;
+use crate::value::Value;
+use crate::vm::{RuntimeError, VM};
+
+/// A handle to an object living in the `Heap`.
+/// Two handles are equal only when they point at the same object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub(crate) usize);
+
+#[derive(Debug)]
+pub enum Obj {
+    String(ObjString),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+    Native(ObjNative),
+    List(ObjList),
+    Map(ObjMap),
+}
+
+#[derive(Debug)]
+pub struct ObjString {
+    pub chars: String,
+}
+
+impl ObjString {
+    pub fn new(chars: String) -> Self {
+        Self { chars }
+    }
+}
+
+/// A compiled function, the top level script is a function without a name
+#[derive(Debug, Default)]
+pub struct ObjFunction {
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    pub name: Option<ObjRef>,
+}
+
+impl ObjFunction {
+    pub fn new(name: Option<ObjRef>) -> Self {
+        Self {
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            name,
+        }
+    }
+}
+
+/// A function together with the variables it captured from enclosing scopes
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+impl ObjClosure {
+    pub fn new(function: ObjRef) -> Self {
+        Self {
+            function,
+            upvalues: Vec::new(),
+        }
+    }
+}
+
+/// A captured variable.
+/// While open it points at a stack slot, once that slot goes away the value moves into `closed`
+#[derive(Debug)]
+pub struct ObjUpvalue {
+    pub location: usize,
+    pub closed: Option<Value>,
+}
+
+impl ObjUpvalue {
+    pub fn new(location: usize) -> Self {
+        Self {
+            location,
+            closed: None,
+        }
+    }
+}
+
+/// A class, `methods` maps method names to their closures
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: ObjRef,
+    pub methods: HashMap<ObjRef, ObjRef>,
+}
+
+impl ObjClass {
+    pub fn new(name: ObjRef) -> Self {
+        Self {
+            name,
+            methods: HashMap::new(),
+        }
+    }
+}
+
+/// An instance of a class, fields are created on first assignment
+#[derive(Debug)]
+pub struct ObjInstance {
+    pub class: ObjRef,
+    pub fields: HashMap<ObjRef, Value>,
+}
+
+impl ObjInstance {
+    pub fn new(class: ObjRef) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+/// A method closure bound to the instance it was accessed on
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+impl ObjBoundMethod {
+    pub fn new(receiver: Value, method: ObjRef) -> Self {
+        Self { receiver, method }
+    }
+}
+
+/// A list handed to scripts by the host, scripts can pass it around and print it
+#[derive(Debug, Default)]
+pub struct ObjList {
+    pub items: Vec<Value>,
+}
+
+impl ObjList {
+    pub fn new(items: Vec<Value>) -> Self {
+        Self { items }
+    }
+}
+
+/// A map handed to scripts by the host, keys are interned strings
+#[derive(Debug, Default)]
+pub struct ObjMap {
+    pub entries: HashMap<ObjRef, Value>,
+}
+
+impl ObjMap {
+    pub fn new(entries: HashMap<ObjRef, Value>) -> Self {
+        Self { entries }
+    }
+}
+
+/// Signature of a host function exposed to scripts
+pub type NativeFn = fn(&mut VM, &[Value]) -> Result<Value, RuntimeError>;
+
+/// A Rust function registered with `VM::define_native`
+pub struct ObjNative {
+    pub name: ObjRef,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl ObjNative {
+    pub fn new(name: ObjRef, arity: usize, function: NativeFn) -> Self {
+        Self { name, arity, function }
+    }
+}
+
+impl fmt::Debug for ObjNative {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ObjNative")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
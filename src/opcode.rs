@@ -1,11 +1,163 @@
+use std::convert::TryFrom;
+
+/// Instructions that take a constant operand have a long form, it takes a three byte index
+/// in place of the one byte one so the instruction still works once a chunk has more than
+/// 256 constants
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
     OpConstant,
+    /// like `OpConstant` but with a 24 bit index, for chunks with more than 256 constants
+    OpConstantLong,
+    OpNil,
+    OpTrue,
+    OpFalse,
+    OpDefineGlobal,
+    OpDefineGlobalLong,
+    OpGetGlobal,
+    OpGetGlobalLong,
+    OpSetGlobal,
+    OpSetGlobalLong,
+    OpGetLocal,
+    OpSetLocal,
+    OpEqual,
+    OpGreater,
+    OpLess,
     OpAdd,
     OpSub,
     OpMult,
     OpDiv,
     OpMod, // modulus operator
+    OpNot,
     OpNegate,
+    OpPrint,
+    OpPop,
+    OpJump,
+    OpJumpIfFalse,
+    OpLoop,
+    OpCall,
+    OpClosure,
+    OpClosureLong,
+    OpGetUpvalue,
+    OpSetUpvalue,
+    OpCloseUpvalue,
+    OpClass,
+    OpClassLong,
+    OpGetProperty,
+    OpGetPropertyLong,
+    OpSetProperty,
+    OpSetPropertyLong,
+    OpMethod,
+    OpMethodLong,
+    OpInvoke,
+    OpInvokeLong,
+    OpInherit,
+    OpGetSuper,
+    OpGetSuperLong,
+    OpSuperInvoke,
+    OpSuperInvokeLong,
     OpReturn,
 }
+
+impl OpCode {
+    /// whether the constant operand is three bytes wide
+    pub fn is_long(self) -> bool {
+        matches!(
+            self,
+            OpCode::OpConstantLong
+                | OpCode::OpDefineGlobalLong
+                | OpCode::OpGetGlobalLong
+                | OpCode::OpSetGlobalLong
+                | OpCode::OpClosureLong
+                | OpCode::OpClassLong
+                | OpCode::OpGetPropertyLong
+                | OpCode::OpSetPropertyLong
+                | OpCode::OpMethodLong
+                | OpCode::OpInvokeLong
+                | OpCode::OpGetSuperLong
+                | OpCode::OpSuperInvokeLong
+        )
+    }
+}
+
+/// every opcode, indexed by its byte
+const OPCODES: [OpCode; 50] = [
+    OpCode::OpConstant,
+    OpCode::OpConstantLong,
+    OpCode::OpNil,
+    OpCode::OpTrue,
+    OpCode::OpFalse,
+    OpCode::OpDefineGlobal,
+    OpCode::OpDefineGlobalLong,
+    OpCode::OpGetGlobal,
+    OpCode::OpGetGlobalLong,
+    OpCode::OpSetGlobal,
+    OpCode::OpSetGlobalLong,
+    OpCode::OpGetLocal,
+    OpCode::OpSetLocal,
+    OpCode::OpEqual,
+    OpCode::OpGreater,
+    OpCode::OpLess,
+    OpCode::OpAdd,
+    OpCode::OpSub,
+    OpCode::OpMult,
+    OpCode::OpDiv,
+    OpCode::OpMod,
+    OpCode::OpNot,
+    OpCode::OpNegate,
+    OpCode::OpPrint,
+    OpCode::OpPop,
+    OpCode::OpJump,
+    OpCode::OpJumpIfFalse,
+    OpCode::OpLoop,
+    OpCode::OpCall,
+    OpCode::OpClosure,
+    OpCode::OpClosureLong,
+    OpCode::OpGetUpvalue,
+    OpCode::OpSetUpvalue,
+    OpCode::OpCloseUpvalue,
+    OpCode::OpClass,
+    OpCode::OpClassLong,
+    OpCode::OpGetProperty,
+    OpCode::OpGetPropertyLong,
+    OpCode::OpSetProperty,
+    OpCode::OpSetPropertyLong,
+    OpCode::OpMethod,
+    OpCode::OpMethodLong,
+    OpCode::OpInvoke,
+    OpCode::OpInvokeLong,
+    OpCode::OpInherit,
+    OpCode::OpGetSuper,
+    OpCode::OpGetSuperLong,
+    OpCode::OpSuperInvoke,
+    OpCode::OpSuperInvokeLong,
+    OpCode::OpReturn,
+];
+
+impl TryFrom<u8> for OpCode {
+    /// the byte that isn't an opcode
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OPCODES.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_decode_to_the_opcode_they_encode() {
+        for byte in 0..=u8::MAX {
+            match OpCode::try_from(byte) {
+                Ok(opcode) => assert_eq!(opcode as u8, byte),
+                Err(rejected) => {
+                    assert_eq!(rejected, byte);
+                    assert!(byte as usize >= OPCODES.len());
+                }
+            }
+        }
+        assert_eq!(OpCode::try_from(OpCode::OpReturn as u8), Ok(OpCode::OpReturn));
+    }
+}
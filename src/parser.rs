@@ -7,6 +7,12 @@ pub struct Parser {
     pub parse_rules: Vec<ParseRule>,
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Parser {
     pub fn new() -> Self {
 
@@ -16,6 +22,7 @@ impl Parser {
                 start: 0,
                 length: 0,
                 line: 0,
+                column: 0,
                 message: None,
             },
             previous: Token {
@@ -23,6 +30,7 @@ impl Parser {
                 start: 0,
                 length: 0,
                 line: 0,
+                column: 0,
                 message: None,
             },
             had_error: false,
@@ -53,6 +61,15 @@ pub enum ParseFunctions {
     Unary,       
     Binary, 
     Number, 
+    Literal,
+    String,
+    Variable,
+    And,
+    Or,
+    Call,
+    Dot,
+    This,
+    Super,
     Null,
 }
 #[derive(Copy, Clone, Debug)]
@@ -77,43 +94,43 @@ impl ParseRule {
 
 fn get_rule_array() -> Vec<ParseRule> {
     let mut rules: Vec<ParseRule> = vec![ParseRule::empty(); 50];
-    rules[TokenType::LEFT_PAREN as usize]    = ParseRule::new( ParseFunctions::Grouping,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::LEFT_PAREN as usize]    = ParseRule::new( ParseFunctions::Grouping,     ParseFunctions::Call,   Precedence::CALL );
     rules[TokenType::RIGHT_PAREN as usize]   = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::LEFT_BRACE as usize]    = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE ); 
     rules[TokenType::RIGHT_BRACE as usize]   = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::COMMA as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::DOT as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::DOT as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Dot,    Precedence::CALL );
     rules[TokenType::MINUS as usize]         = ParseRule::new( ParseFunctions::Unary,    ParseFunctions::Binary, Precedence::TERM );
     rules[TokenType::PLUS as usize]          = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::TERM );
     rules[TokenType::SEMICOLON as usize]     = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::SLASH as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::FACTOR);
     rules[TokenType::STAR as usize]          = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::FACTOR);
     rules[TokenType::MOD as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::FACTOR);
-    rules[TokenType::BANG as usize]          = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::BANG_EQUAL as usize]    = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::BANG as usize]          = ParseRule::new( ParseFunctions::Unary,    ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::BANG_EQUAL as usize]    = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::EQUALITY );
     rules[TokenType::EQUAL as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::EQUAL_EQUAL as usize]   = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::GREATER as usize]       = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::GREATER_EQUAL as usize] = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::LESS as usize]          = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::LESS_EQUAL as usize]    = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::IDENTIFIER as usize]    = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::STRING as usize]        = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::EQUAL_EQUAL as usize]   = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::EQUALITY );
+    rules[TokenType::GREATER as usize]       = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::COMPARISON );
+    rules[TokenType::GREATER_EQUAL as usize] = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::COMPARISON );
+    rules[TokenType::LESS as usize]          = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::COMPARISON );
+    rules[TokenType::LESS_EQUAL as usize]    = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Binary, Precedence::COMPARISON );
+    rules[TokenType::IDENTIFIER as usize]    = ParseRule::new( ParseFunctions::Variable, ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::STRING as usize]        = ParseRule::new( ParseFunctions::String,   ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::NUMBER as usize]        = ParseRule::new( ParseFunctions::Number,   ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::AND as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::AND as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::And,    Precedence::AND );
     rules[TokenType::CLASS as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::ELSE as usize]          = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::FALSE as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::FALSE as usize]         = ParseRule::new( ParseFunctions::Literal,  ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::FOR as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::FUN as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::IF as usize]            = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::NIL as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::OR as usize]            = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::NIL as usize]           = ParseRule::new( ParseFunctions::Literal,  ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::OR as usize]            = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Or,     Precedence::OR );
     rules[TokenType::PRINT as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::RETURN as usize]        = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::SUPER as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::THIS as usize]          = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
-    rules[TokenType::TRUE as usize]          = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::SUPER as usize]         = ParseRule::new( ParseFunctions::Super,    ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::THIS as usize]          = ParseRule::new( ParseFunctions::This,     ParseFunctions::Null,   Precedence::NONE );
+    rules[TokenType::TRUE as usize]          = ParseRule::new( ParseFunctions::Literal,  ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::VAR as usize]           = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::WHILE as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
     rules[TokenType::ERROR as usize]         = ParseRule::new( ParseFunctions::Null,     ParseFunctions::Null,   Precedence::NONE );
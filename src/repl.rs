@@ -0,0 +1,169 @@
+//! The interactive prompt started when rustmox is run without a script.
+
+use std::io;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use rustmox::compiler::Compiler;
+use rustmox::heap::Heap;
+use rustmox::scanner::Scanner;
+use rustmox::token::TokenType;
+use rustmox::vm::{InterpretResult, Settings, VM};
+
+/// name diagnostics use for code typed at the prompt
+const REPL_NAME: &str = "<repl>";
+
+const HELP: &str = ":dis [code]     print the bytecode of code, or of the last input
+:tokens [code]  print the tokens of code, or of the last input
+:quit           leave the repl";
+
+/// one vm for the whole session, so globals defined on one line are visible on the next
+pub struct Repl {
+    vm: VM,
+    last_input: String,
+}
+
+impl Repl {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            vm: VM::with_settings(Heap::new(), settings),
+            last_input: String::new(),
+        }
+    }
+
+    /// reads lines until `:quit` or end of input, an input is only run once all of its
+    /// braces and parentheses are closed
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut editor = DefaultEditor::new().map_err(to_io_error)?;
+        let mut buffer = String::new();
+
+        loop {
+            let prompt = if buffer.is_empty() { "> " } else { "... " };
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                // ctrl-c throws away whatever was typed so far
+                Err(ReadlineError::Interrupted) => {
+                    buffer.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(error) => return Err(to_io_error(error)),
+            };
+
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                let _ = editor.add_history_entry(line.as_str());
+                if !self.command(line.trim()) {
+                    break;
+                }
+                continue;
+            }
+
+            buffer.push_str(&line);
+            buffer.push('\n');
+            if buffer.trim().is_empty() {
+                buffer.clear();
+                continue;
+            }
+            if is_unbalanced(&buffer) {
+                continue;
+            }
+
+            let _ = editor.add_history_entry(buffer.trim_end());
+            // drop the last newline so errors at the end point at the last line typed
+            let input = std::mem::take(&mut buffer).trim_end().to_string();
+            self.evaluate(&input);
+            self.last_input = input;
+        }
+        Ok(())
+    }
+
+    /// runs a meta-command, returns false when the repl should stop
+    fn command(&mut self, line: &str) -> bool {
+        let (command, argument) = match line.find(char::is_whitespace) {
+            Some(index) => (&line[..index], line[index..].trim()),
+            None => (line, ""),
+        };
+        let code = if argument.is_empty() { self.last_input.clone() } else { argument.to_string() };
+
+        match command {
+            ":quit" | ":q" => return false,
+            ":dis" => self.disassemble(&code),
+            ":tokens" => print_tokens(&code),
+            ":help" => println!("{}", HELP),
+            _ => eprintln!("Unknown command {}, try :help", command),
+        }
+        true
+    }
+
+    fn evaluate(&mut self, input: &str) {
+        match self.vm.compile_repl(input.to_string()) {
+            Ok(function) => {
+                // the vm has already cleared its stack, the globals survive
+                if let InterpretResult::InterpretRuntimeError(error) = self.vm.interpret(function) {
+                    eprintln!("{}", error);
+                }
+            }
+            Err(diagnostics) => {
+                for diagnostic in diagnostics.iter() {
+                    eprint!("{}", diagnostic.render(REPL_NAME, input));
+                }
+            }
+        }
+    }
+
+    /// compiles code without running it, the compiler prints each function as it finishes
+    fn disassemble(&mut self, code: &str) {
+        let mut stdout = io::stdout();
+        let compiled = Compiler::from_source(code.to_string(), self.vm.heap_mut())
+            .repl()
+            .print_code(&mut stdout)
+            .compile();
+
+        if let Err(diagnostics) = compiled {
+            for diagnostic in diagnostics.iter() {
+                eprint!("{}", diagnostic.render(REPL_NAME, code));
+            }
+        }
+    }
+}
+
+fn print_tokens(code: &str) {
+    let chars: Vec<char> = code.chars().collect();
+    let mut scanner = Scanner::new(code);
+    loop {
+        let token = scanner.scan_token();
+        let lexeme: String = match &token.message {
+            Some(message) => message.clone(),
+            None => chars[token.start..token.start + token.length].iter().collect(),
+        };
+        let token_type = format!("{:?}", token.token_type);
+        println!("{:>4}:{:<3} {:<14} '{}'", token.line, token.column, token_type, lexeme);
+
+        if token.token_type == TokenType::EOF {
+            break;
+        }
+    }
+}
+
+/// true while there are more opening braces or parentheses than closing ones
+fn is_unbalanced(input: &str) -> bool {
+    let mut scanner = Scanner::new(input);
+    let mut depth: i64 = 0;
+    loop {
+        match scanner.scan_token().token_type {
+            TokenType::LEFT_BRACE | TokenType::LEFT_PAREN => depth += 1,
+            TokenType::RIGHT_BRACE | TokenType::RIGHT_PAREN => depth -= 1,
+            TokenType::EOF => break,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+fn to_io_error(error: ReadlineError) -> io::Error {
+    match error {
+        ReadlineError::Io(error) => error,
+        error => io::Error::other(error),
+    }
+}
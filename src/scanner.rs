@@ -8,6 +8,11 @@ pub struct Scanner {
     current: usize,
     source_vec: Vec<char>,
     line: u64,
+    // offset of the first character on the current line
+    line_start: usize,
+    // where the token being scanned begins, a string may end on a later line
+    start_line: u64,
+    start_column: usize,
 }
 
 impl Scanner {
@@ -17,15 +22,20 @@ impl Scanner {
             start: 0,
             current: 0,
             source_vec: source.chars().collect(),
-            line: 0,
+            line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
     /// scans a token
     pub fn scan_token(&mut self) -> Token {
-        self.start = self.current;
-
         self.skip_whitespace();
 
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.start - self.line_start + 1;
+
         if self.is_end() {
             return self.generate_token(TokenType::EOF);
         }
@@ -67,7 +77,7 @@ impl Scanner {
                 } else {
                     TokenType::LESS
                 };
-                return self.generate_token(token);
+                self.generate_token(token)
             }
             '>' => {
                 let token = if self.match_char('=') {
@@ -83,20 +93,21 @@ impl Scanner {
             _ => self.error_token("Unexpected character"),
         }
     }
-    /// checks if scanner.current is at end.
+    /// checks if scanner.current is past the last character, a '\0' in the source is just
+    /// an unexpected character
     fn is_end(&self) -> bool {
-        
-        return self.source_vec[self.current] == '\0';
+        self.current >= self.source_vec.len()
     }
+    /// the current character, '\0' once we run out of source
     fn peek(&self) -> char {
-        self.source_vec[self.current]
+        self.source_vec.get(self.current).copied().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
         if self.is_end() {
             return '\0';
         }
-        self.source_vec[self.current + 1]
+        self.source_vec.get(self.current + 1).copied().unwrap_or('\0')
     }
 
     fn match_char(&mut self, expected: char) -> bool {
@@ -104,12 +115,12 @@ impl Scanner {
             return false;
         }
 
-        if self.source_vec[self.current] != expected {
+        if self.peek() != expected {
             return false;
         }
 
         self.current += 1;
-        return true;
+        true
     }
 
     fn skip_whitespace(&mut self) {
@@ -119,34 +130,33 @@ impl Scanner {
             match c {
                 ' ' | '\t' | '\r' => {
                     self.advance();
-                    break;
                 }
                 '\n' => {
-                    self.line += 1;
                     self.advance();
-                    break;
+                    self.new_line();
                 }
-                '/' => {
-                    if self.peek_next() == '/' {
-                        while self.peek() != '\n' && !self.is_end() {
-                            self.advance();
-                        }
-                    } else {
-                        return;
+                '/' if self.peek_next() == '/' => {
+                    while self.peek() != '\n' && !self.is_end() {
+                        self.advance();
                     }
-                    break;
                 }
                 _ => break,
             }
         }
     }
 
+    /// call right after consuming a newline
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
     fn handle_string(&mut self) -> Token {
         while self.peek() != '"' && !self.is_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            let c = self.advance();
+            if c == '\n' {
+                self.new_line();
             }
-            self.advance();
         }
 
         if self.is_end() {
@@ -172,7 +182,7 @@ impl Scanner {
     }
 
     fn handle_identifier(&mut self) -> Token {
-        while self.peek().is_alphanumeric() {
+        while self.peek().is_alphanumeric() || self.peek() == '_' {
             self.advance();
         }
         let token_type = self.get_identifier_type();
@@ -180,8 +190,7 @@ impl Scanner {
     }
 
     fn get_identifier_type(&mut self) -> TokenType {
-        let c;
-        c = self.source_vec[self.start];
+        let c = self.source_vec[self.start];
 
         match c {
             'a' => self.check_keyword(1, 2, "nd", TokenType::AND),
@@ -196,41 +205,41 @@ impl Scanner {
             'd' => self.check_keyword(1, 2, "ec", TokenType::VAR),
             'w' => self.check_keyword(1, 4, "hile", TokenType::WHILE),
             'f' => {
-                if self.current as u8 - self.start as u8 > 1 {
-                    match self.source_vec[self.current + 1] {
+                if self.current - self.start > 1 {
+                    match self.source_vec[self.start + 1] {
                         'a' => self.check_keyword(2, 3, "lse", TokenType::FALSE),
                         'o' => self.check_keyword(2, 1, "r", TokenType::FOR),
                         'u' => self.check_keyword(2, 2, "nc", TokenType::FUN),
-                        _ => return TokenType::IDENTIFIER,
+                        _ => TokenType::IDENTIFIER,
                     }
                 } else {
-                    return TokenType::IDENTIFIER;
+                    TokenType::IDENTIFIER
                 }
             }
             't' => {
-                if self.current as u8 - self.start as u8 > 1 {
-                    match self.source_vec[self.current + 1] {
+                if self.current - self.start > 1 {
+                    match self.source_vec[self.start + 1] {
                         'h' => self.check_keyword(2, 2, "is", TokenType::THIS),
                         'r' => self.check_keyword(2, 2, "ue", TokenType::TRUE),
-                        _ => return TokenType::IDENTIFIER,
+                        _ => TokenType::IDENTIFIER,
                     }
                 } else {
-                    return TokenType::IDENTIFIER;
+                    TokenType::IDENTIFIER
                 }
             }
 
-            _ => return TokenType::IDENTIFIER,
+            _ => TokenType::IDENTIFIER,
         }
     }
 
     fn check_keyword(
         &mut self,
-        start: u8,
+        start: usize,
         length: usize,
         rest: &str,
         token_type: TokenType,
     ) -> TokenType {
-        if self.current as u8 - self.start as u8 == start + length as u8 {
+        if self.current - self.start == start + length {
             let mut correct = true;
             for (index, character) in rest.chars().rev().enumerate() {
                 let i = self.current - index - 1;
@@ -245,15 +254,16 @@ impl Scanner {
             }
         }
 
-        return TokenType::IDENTIFIER;
+        TokenType::IDENTIFIER
     }
     /// it generates a token from a scan.
     fn generate_token(&self, token_type: TokenType) -> Token {
         Token {
             token_type,
             start: self.start,
-            length: (self.current as usize - self.start as usize),
-            line: self.line,
+            length: (self.current - self.start),
+            line: self.start_line,
+            column: self.start_column,
             message: None,
         }
     }
@@ -267,9 +277,37 @@ impl Scanner {
         Token {
             token_type: TokenType::ERROR,
             start: self.start,
-            length: message.len(),
-            line: self.line,
+            length: self.current - self.start,
+            line: self.start_line,
+            column: self.start_column,
             message: Some(message.to_string()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_nul_is_an_error_not_the_end() {
+        let mut scanner = Scanner::new("print 1;\0print 2;");
+        let types: Vec<TokenType> = std::iter::from_fn(|| {
+            let token = scanner.scan_token();
+            (token.token_type != TokenType::EOF).then_some(token.token_type)
+        })
+        .collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::PRINT,
+                TokenType::NUMBER,
+                TokenType::SEMICOLON,
+                TokenType::ERROR,
+                TokenType::PRINT,
+                TokenType::NUMBER,
+                TokenType::SEMICOLON,
+            ]
+        );
+    }
+}
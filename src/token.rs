@@ -53,12 +53,35 @@ pub enum TokenType {
     EOF,
 }
 
+/// A range of character offsets into the source, `end` is exclusive.
+/// line and column are where `start` is, so nothing has to scan the source again to find it
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u64,
+    pub column: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub start: usize,
     pub length: usize,
     pub line: u64,
+    /// 1-based column of the first character
+    pub column: usize,
     pub message: Option<String>
 }
 
+impl Token {
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.start + self.length,
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
@@ -1,13 +1,27 @@
+use std::fmt;
+
+use crate::heap::Heap;
+use crate::object::{Obj, ObjRef};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ValueType {
     Bool(bool),
     Number(f64),
+    Obj(ObjRef),
     Nil
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Value {
     pub value: ValueType
 }
 
+impl Default for Value {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Value {
     pub fn new() -> Self {
         Self { value: ValueType::Nil }
@@ -18,30 +32,132 @@ impl Value {
     pub fn from_float(value: f64) -> Self {
         Self { value: ValueType::Number(value) }
     }
+    pub fn from_obj(value: ObjRef) -> Self {
+        Self { value: ValueType::Obj(value) }
+    }
     pub fn as_bool(&self) -> Result<bool, &str> {
         if let ValueType::Bool(b) = self.value {
-            return Ok(b);
+            Ok(b)
         }
         else {
-            return Err("something went wrong")
+            Err("something went wrong")
         }
     }
 
     pub fn as_float(&self) -> Result<f64, &str> {
         if let ValueType::Number(n) = self.value {
-            return Ok(n);
+            Ok(n)
         }
         else {
-            return Err("something went wrong")
+            Err("something went wrong")
         }
     }
 
-    pub fn print(&self) {
+    pub fn as_obj(&self) -> Result<ObjRef, &str> {
+        if let ValueType::Obj(o) = self.value {
+            Ok(o)
+        }
+        else {
+            Err("something went wrong")
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self.value, ValueType::Number(_))
+    }
+
+    pub fn is_string(&self, heap: &Heap) -> bool {
         match self.value {
-            ValueType::Number(n) =>    println!("{}", n),
-            ValueType::Bool(n) =>    println!("{}", n),
-            ValueType::Nil =>    println!("nil")
+            ValueType::Obj(o) => matches!(heap.get(o), Obj::String(_)),
+            _ => false,
         }
-     
     }
-}
\ No newline at end of file
+
+    pub fn is_instance(&self, heap: &Heap) -> bool {
+        match self.value {
+            ValueType::Obj(o) => matches!(heap.get(o), Obj::Instance(_)),
+            _ => false,
+        }
+    }
+
+    pub fn is_class(&self, heap: &Heap) -> bool {
+        match self.value {
+            ValueType::Obj(o) => matches!(heap.get(o), Obj::Class(_)),
+            _ => false,
+        }
+    }
+
+    pub fn is_closure(&self, heap: &Heap) -> bool {
+        match self.value {
+            ValueType::Obj(o) => matches!(heap.get(o), Obj::Closure(_)),
+            _ => false,
+        }
+    }
+
+    /// nil and false are falsey, every other value is truthy
+    pub fn is_falsey(&self) -> bool {
+        matches!(self.value, ValueType::Nil | ValueType::Bool(false))
+    }
+
+    /// objects can only be printed with access to the heap they live in
+    pub fn display<'a>(&self, heap: &'a Heap) -> ValueDisplay<'a> {
+        ValueDisplay { value: *self, heap }
+    }
+}
+
+pub struct ValueDisplay<'a> {
+    value: Value,
+    heap: &'a Heap,
+}
+
+impl<'a> fmt::Display for ValueDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value.value {
+            ValueType::Number(n) => write!(f, "{}", n),
+            ValueType::Bool(b) => write!(f, "{}", b),
+            ValueType::Nil => write!(f, "nil"),
+            ValueType::Obj(o) => match self.heap.get(o) {
+                Obj::String(string) => write!(f, "{}", string.chars),
+                Obj::Function(function) => match function.name {
+                    Some(name) => write!(f, "<fn {}>", self.heap.as_string(name).chars),
+                    None => write!(f, "<script>"),
+                },
+                Obj::Closure(closure) => write!(f, "{}", Value::from_obj(closure.function).display(self.heap)),
+                Obj::Upvalue(_) => write!(f, "upvalue"),
+                Obj::Class(class) => write!(f, "{}", self.heap.as_string(class.name).chars),
+                Obj::Instance(instance) => {
+                    let class = self.heap.as_class(instance.class);
+                    write!(f, "{} instance", self.heap.as_string(class.name).chars)
+                }
+                Obj::Native(_) => write!(f, "<native fn>"),
+                Obj::BoundMethod(bound) => write!(f, "{}", Value::from_obj(bound.method).display(self.heap)),
+                Obj::List(list) => {
+                    write!(f, "[")?;
+                    for (i, item) in list.items.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", item.display(self.heap))?;
+                    }
+                    write!(f, "]")
+                }
+                Obj::Map(map) => {
+                    // sorted by key so the same map always prints the same way
+                    let mut entries: Vec<(&str, Value)> = map.entries.iter()
+                        .map(|(&key, &value)| (self.heap.as_string(key).chars.as_str(), value))
+                        .collect();
+                    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+                    write!(f, "{{")?;
+                    for (i, (key, value)) in entries.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}: {}", key, value.display(self.heap))?;
+                    }
+                    write!(f, "}}")
+                }
+            },
+        }
+    }
+}
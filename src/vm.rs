@@ -1,89 +1,963 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
 use crate::chunk::Chunk;
+use crate::compiler::Compiler;
+use crate::diagnostic::Diagnostic;
+use crate::disassembler::Disassembler;
+use crate::heap::{Heap, GC_INITIAL_THRESHOLD};
+use crate::native;
+use crate::object::{NativeFn, Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjInstance, ObjNative, ObjRef, ObjUpvalue};
 use crate::opcode::OpCode;
+use crate::value::Value;
+
+const FRAMES_MAX: usize = 64;
 
+/// How a vm runs, usually put together with `Settings::builder()`
 pub struct Settings {
-    debug: bool,
+    /// print the stack and the next instruction before every instruction runs
+    pub trace_execution: bool,
+    /// disassemble every function once the compiler has finished it
+    pub print_code: bool,
+    /// how many frames a runtime error's backtrace keeps, innermost first
+    pub backtrace_depth: usize,
+    /// collect garbage before every allocation, useful to shake out objects that are not rooted
+    pub stress_gc: bool,
+    /// bytes the heap may grow to before the first collection
+    pub gc_threshold: usize,
+    /// where execution traces and bytecode dumps are written
+    pub trace_output: Box<dyn Write>,
+    /// where `print` writes
+    pub stdout: Box<dyn Write>,
+    /// the error stream natives defined by the host can write to, see `VM::stderr`
+    pub stderr: Box<dyn Write>,
+    /// the input natives defined by the host can read from, see `VM::stdin`
+    pub stdin: Box<dyn BufRead>,
+    /// set when `stdout` is being captured, see `SettingsBuilder::capture_output`
+    pub captured: Option<Capture>,
 }
 
-pub struct VM {
-    pub chunk: Chunk,
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            trace_execution: false,
+            print_code: false,
+            backtrace_depth: FRAMES_MAX,
+            stress_gc: false,
+            gc_threshold: GC_INITIAL_THRESHOLD,
+            trace_output: Box::new(io::stderr()),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            stdin: Box::new(io::BufReader::new(io::stdin())),
+            captured: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder::default()
+    }
+}
+
+/// Builds `Settings`, anything not set keeps its default
+#[derive(Default)]
+pub struct SettingsBuilder {
+    settings: Settings,
+}
+
+impl SettingsBuilder {
+    pub fn trace_execution(mut self, trace_execution: bool) -> Self {
+        self.settings.trace_execution = trace_execution;
+        self
+    }
+
+    pub fn print_code(mut self, print_code: bool) -> Self {
+        self.settings.print_code = print_code;
+        self
+    }
+
+    pub fn backtrace_depth(mut self, backtrace_depth: usize) -> Self {
+        self.settings.backtrace_depth = backtrace_depth;
+        self
+    }
+
+    pub fn stress_gc(mut self, stress_gc: bool) -> Self {
+        self.settings.stress_gc = stress_gc;
+        self
+    }
+
+    pub fn gc_threshold(mut self, gc_threshold: usize) -> Self {
+        self.settings.gc_threshold = gc_threshold;
+        self
+    }
+
+    /// sends traces and bytecode dumps to `output` instead of stderr
+    pub fn trace_output(mut self, output: impl Write + 'static) -> Self {
+        self.settings.trace_output = Box::new(output);
+        self
+    }
+
+    pub fn stdout(mut self, stdout: impl Write + 'static) -> Self {
+        self.settings.stdout = Box::new(stdout);
+        self.settings.captured = None;
+        self
+    }
+
+    pub fn stderr(mut self, stderr: impl Write + 'static) -> Self {
+        self.settings.stderr = Box::new(stderr);
+        self
+    }
+
+    pub fn stdin(mut self, stdin: impl BufRead + 'static) -> Self {
+        self.settings.stdin = Box::new(stdin);
+        self
+    }
+
+    /// keeps everything the script prints, read it back with `VM::captured_output`
+    pub fn capture_output(mut self) -> Self {
+        let capture = Capture::new();
+        self.settings.stdout = Box::new(capture.clone());
+        self.settings.captured = Some(capture);
+        self
+    }
+
+    pub fn build(self) -> Settings {
+        self.settings
+    }
+}
+
+/// An output sink that keeps everything written to it, clones share the same buffer
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// everything written since the last call
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.buffer.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// an ongoing function call.
+/// `slots` is where the called function's stack window starts
+struct CallFrame {
+    closure: ObjRef,
+    function: ObjRef,
     pc: usize,
+    slots: usize,
+}
+
+pub struct VM {
+    frames: Vec<CallFrame>,
     settings: Settings,
-    stack: Vec<f64>,
+    stack: Vec<Value>,
+    heap: Heap,
+    globals: HashMap<ObjRef, Value>,
+    // upvalues still pointing at the stack, ordered by stack slot
+    open_upvalues: Vec<ObjRef>,
+    init_string: ObjRef,
 }
 
+/// An error raised while running a script.
+/// natives only fill in `message`, the vm adds the line and backtrace when it unwinds
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: u64,
+    /// innermost call first
+    pub backtrace: Vec<TraceFrame>,
+}
+
+/// one active call at the time a runtime error was raised
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// `None` for the top level script
+    pub function: Option<String>,
+    pub line: u64,
+}
+
+impl RuntimeError {
+    pub fn new(message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+            line: 0,
+            backtrace: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in self.backtrace.iter() {
+            match &frame.function {
+                Some(name) => write!(f, "\n[line {}] in {}()", frame.line, name)?,
+                None => write!(f, "\n[line {}] in script", frame.line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[derive(Debug)]
 pub enum InterpretResult {
     InterpretOk,
-    InterpretCompileError,
-    InterpretRuntimeError,
+    InterpretCompileError(Vec<Diagnostic>),
+    InterpretRuntimeError(RuntimeError),
 }
 
 impl VM {
-    pub fn new(chunk: Chunk) -> Self {
-        VM {
-            chunk: chunk,
-            pc: 0,
-            settings: Settings { debug: true },
+    pub fn new(heap: Heap) -> Self {
+        VM::with_settings(heap, Settings::default())
+    }
+    pub fn with_settings(mut heap: Heap, settings: Settings) -> Self {
+        heap.next_gc = settings.gc_threshold;
+        let init_string = heap.copy_string("init");
+        let mut vm = VM {
+            frames: Vec::new(),
+            settings,
             stack: Vec::new(),
+            heap,
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            init_string,
+        };
+        vm.define_native("clock", 0, native::clock);
+        vm
+    }
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+    /// the compiler allocates its constants straight into the heap of the vm that will run them
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+    /// compiles a script into this vm's heap, dumping its bytecode if the settings ask for it
+    pub fn compile(&mut self, source: String) -> Result<ObjRef, Vec<Diagnostic>> {
+        self.compile_with(source, false)
+    }
+    /// compiles a line typed into the repl, see `Compiler::repl`
+    pub fn compile_repl(&mut self, source: String) -> Result<ObjRef, Vec<Diagnostic>> {
+        self.compile_with(source, true)
+    }
+    fn compile_with(&mut self, source: String, repl: bool) -> Result<ObjRef, Vec<Diagnostic>> {
+        let mut compiler = Compiler::from_source(source, &mut self.heap);
+        if self.settings.print_code {
+            compiler = compiler.print_code(self.settings.trace_output.as_mut());
         }
+        if repl {
+            compiler = compiler.repl();
+        }
+        compiler.compile()
+    }
+    pub fn stdout(&mut self) -> &mut dyn Write {
+        self.settings.stdout.as_mut()
+    }
+    pub fn stderr(&mut self) -> &mut dyn Write {
+        self.settings.stderr.as_mut()
     }
-    pub fn update_chunk(&mut self, chunk: Chunk) {
-        self.chunk = chunk;
+    pub fn stdin(&mut self) -> &mut dyn BufRead {
+        self.settings.stdin.as_mut()
     }
-    pub fn interpret(&mut self) -> InterpretResult {
-        self.run()
+    /// what the script printed since the last call, `None` unless output is captured
+    pub fn captured_output(&self) -> Option<String> {
+        self.settings.captured.as_ref().map(Capture::take)
     }
-    fn run(&mut self) -> InterpretResult {
+    pub fn global(&self, name: &str) -> Option<Value> {
+        let name = self.heap.find_string(name)?;
+        self.globals.get(&name).copied()
+    }
+    /// defines or overwrites the global `name`
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        // copying the name never collects, so `value` can't be freed under us
+        let name = self.heap.copy_string(name);
+        self.globals.insert(name, value);
+    }
+    /// calls a function, native, bound method or class from the host and returns its result
+    pub fn call_function(&mut self, callee: Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let base = self.frames.len();
+        self.stack.push(callee);
+        self.stack.extend_from_slice(args);
+        self.call_value(callee, args.len())?;
+        // natives and classes without an initializer return without pushing a frame
+        if self.frames.len() > base {
+            self.run(base)?;
+        }
+        self.pop()
+    }
+    /// exposes a Rust function to scripts as a global called `name`
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        // keep both objects on the stack so a collection in between can't free them
+        let name = self.heap.copy_string(name);
+        self.stack.push(Value::from_obj(name));
+        let native = self.alloc(Obj::Native(ObjNative::new(name, arity, function)));
+        self.stack.push(Value::from_obj(native));
+
+        self.globals.insert(name, Value::from_obj(native));
+        self.stack.pop();
+        self.stack.pop();
+    }
+    /// runs a compiled top level script
+    pub fn interpret(&mut self, function: ObjRef) -> InterpretResult {
+        match self.execute(function) {
+            Ok(()) => InterpretResult::InterpretOk,
+            Err(error) => InterpretResult::InterpretRuntimeError(error),
+        }
+    }
+    fn execute(&mut self, function: ObjRef) -> Result<(), RuntimeError> {
+        // keep the function reachable while the closure is allocated
+        self.stack.push(Value::from_obj(function));
+        let closure = self.alloc(Obj::Closure(ObjClosure::new(function)));
+        self.pop()?;
+        self.stack.push(Value::from_obj(closure));
+        self.call(closure, 0)?;
+        self.run(0)?;
+        // the script's own return value
+        self.pop()?;
+        Ok(())
+    }
+    /// runs until the frame count drops back to `base`, leaving the returned value on the stack
+    fn run(&mut self, base: usize) -> Result<(), RuntimeError> {
         loop {
-            //debug instruction
-            if self.settings.debug {
-                println!("    ");
-                for x in self.stack.iter() {
-                    print!("['{}']", x);
-                }
+            if self.settings.trace_execution {
+                self.trace_instruction()
+                    .map_err(|error| self.runtime_error(&format!("Could not write the trace: {}", error)))?;
             }
 
-            let instruction: OpCode = unsafe { std::mem::transmute(self.chunk.code[self.pc]) };
-            self.increment_counter();
-            // incrementing pc here after getting the first value
+            let instruction = match OpCode::try_from(self.read_byte()) {
+                Ok(instruction) => instruction,
+                Err(byte) => return Err(self.runtime_error(&format!("Unknown opcode {}.", byte))),
+            };
             match instruction {
-                OpCode::OpAdd => self.binary("add"),
-                OpCode::OpSub => self.binary("sub"),
-                OpCode::OpMult => self.binary("mult"),
-                OpCode::OpDiv => self.binary("divide"),
-                OpCode::OpMod => self.binary("mod"),
+                OpCode::OpAdd => self.binary("add")?,
+                OpCode::OpSub => self.binary("sub")?,
+                OpCode::OpMult => self.binary("mult")?,
+                OpCode::OpDiv => self.binary("divide")?,
+                OpCode::OpMod => self.binary("mod")?,
+                OpCode::OpGreater => self.binary("greater")?,
+                OpCode::OpLess => self.binary("less")?,
+                OpCode::OpEqual => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.stack.push(Value::from_bool(a == b));
+                }
+                OpCode::OpNot => {
+                    let value = self.pop()?;
+                    self.stack.push(Value::from_bool(value.is_falsey()));
+                }
                 OpCode::OpConstant => {
-                    // incrementing pc here after getting the value of self.chunk.code
-                    let number = self.chunk.code[self.pc] as usize;
-                    self.increment_counter();
-                    let constant = self.chunk.constants[number];
+                    let constant = self.read_constant();
+                    self.stack.push(constant);
+                }
+                OpCode::OpConstantLong => {
+                    let constant = self.read_constant_long();
                     self.stack.push(constant);
                 }
+                OpCode::OpDefineGlobal | OpCode::OpDefineGlobalLong => {
+                    let name = self.read_string(instruction);
+                    let value = self.pop()?;
+                    self.globals.insert(name, value);
+                }
+                OpCode::OpGetGlobal | OpCode::OpGetGlobalLong => {
+                    let name = self.read_string(instruction);
+                    match self.globals.get(&name) {
+                        Some(&value) => self.stack.push(value),
+                        None => {
+                            let message = format!("Undefined variable '{}'.", self.heap.as_string(name).chars);
+                            return Err(self.runtime_error(&message));
+                        }
+                    }
+                }
+                OpCode::OpSetGlobal | OpCode::OpSetGlobalLong => {
+                    let name = self.read_string(instruction);
+                    if !self.globals.contains_key(&name) {
+                        let message = format!("Undefined variable '{}'.", self.heap.as_string(name).chars);
+                        return Err(self.runtime_error(&message));
+                    }
+                    let value = self.peek(0)?;
+                    self.globals.insert(name, value);
+                }
+                OpCode::OpGetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack.push(self.stack[slot]);
+                }
+                OpCode::OpSetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0)?;
+                }
+                OpCode::OpNil => self.stack.push(Value::new()),
+                OpCode::OpTrue => self.stack.push(Value::from_bool(true)),
+                OpCode::OpFalse => self.stack.push(Value::from_bool(false)),
                 OpCode::OpNegate => {
-                    let negate_num = self.stack.pop().unwrap();
-                    self.stack.push(-negate_num);
+                    let negate_num = match self.peek(0)?.as_float() {
+                        Ok(n) => n,
+                        Err(_) => return Err(self.runtime_error("Operand must be a number.")),
+                    };
+                    self.pop()?;
+                    self.stack.push(Value::from_float(-negate_num));
+                }
+                OpCode::OpPrint => {
+                    let value = self.pop()?;
+                    writeln!(self.settings.stdout, "{}", value.display(&self.heap))
+                        .map_err(|error| self.runtime_error(&format!("Could not write output: {}", error)))?;
+                }
+                OpCode::OpPop => {
+                    self.pop()?;
+                }
+                OpCode::OpJump => {
+                    let offset = self.read_short() as usize;
+                    self.frame_mut().pc += offset;
+                }
+                OpCode::OpJumpIfFalse => {
+                    let offset = self.read_short() as usize;
+                    if self.peek(0)?.is_falsey() {
+                        self.frame_mut().pc += offset;
+                    }
+                }
+                OpCode::OpLoop => {
+                    let offset = self.read_short() as usize;
+                    self.frame_mut().pc -= offset;
+                }
+                OpCode::OpCall => {
+                    let arg_count = self.read_byte() as usize;
+                    let callee = self.peek(arg_count)?;
+                    self.call_value(callee, arg_count)?;
+                }
+                OpCode::OpClosure | OpCode::OpClosureLong => {
+                    let function = self.read_operand(instruction).as_obj().unwrap();
+                    let mut closure = ObjClosure::new(function);
+
+                    for _ in 0..self.heap.as_function(function).upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.heap.as_closure(self.frame().closure).upvalues[index]
+                        };
+                        closure.upvalues.push(upvalue);
+                    }
+
+                    let closure = self.alloc(Obj::Closure(closure));
+                    self.stack.push(Value::from_obj(closure));
+                }
+                OpCode::OpGetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = self.heap.as_closure(self.frame().closure).upvalues[slot];
+                    let value = match self.heap.as_upvalue(upvalue) {
+                        ObjUpvalue { closed: Some(value), .. } => *value,
+                        ObjUpvalue { location, .. } => self.stack[*location],
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::OpSetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = self.heap.as_closure(self.frame().closure).upvalues[slot];
+                    let value = self.peek(0)?;
+                    let upvalue = self.heap.as_upvalue_mut(upvalue);
+                    match upvalue.closed {
+                        Some(_) => upvalue.closed = Some(value),
+                        None => self.stack[upvalue.location] = value,
+                    }
+                }
+                OpCode::OpCloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop()?;
+                }
+                OpCode::OpClass | OpCode::OpClassLong => {
+                    let name = self.read_string(instruction);
+                    let class = self.alloc(Obj::Class(ObjClass::new(name)));
+                    self.stack.push(Value::from_obj(class));
+                }
+                OpCode::OpGetProperty | OpCode::OpGetPropertyLong => {
+                    if !self.peek(0)?.is_instance(&self.heap) {
+                        return Err(self.runtime_error("Only instances have properties."));
+                    }
+                    let instance = self.peek(0)?.as_obj().unwrap();
+                    let name = self.read_string(instruction);
+
+                    // fields shadow methods
+                    if let Some(&value) = self.heap.as_instance(instance).fields.get(&name) {
+                        self.pop()?;
+                        self.stack.push(value);
+                    } else {
+                        let class = self.heap.as_instance(instance).class;
+                        self.bind_method(class, name)?;
+                    }
+                }
+                OpCode::OpSetProperty | OpCode::OpSetPropertyLong => {
+                    if !self.peek(1)?.is_instance(&self.heap) {
+                        return Err(self.runtime_error("Only instances have fields."));
+                    }
+                    let instance = self.peek(1)?.as_obj().unwrap();
+                    let name = self.read_string(instruction);
+                    let value = self.peek(0)?;
+                    self.heap.as_instance_mut(instance).fields.insert(name, value);
+
+                    // leave the assigned value as the result of the expression
+                    let value = self.pop()?;
+                    self.pop()?;
+                    self.stack.push(value);
+                }
+                OpCode::OpMethod | OpCode::OpMethodLong => {
+                    let name = self.read_string(instruction);
+                    let (method, class) = (self.peek(0)?, self.peek(1)?);
+                    // the compiler always emits a class and a closure here, a loaded file may not
+                    if !class.is_class(&self.heap) {
+                        return Err(self.runtime_error("Only classes have methods."));
+                    }
+                    if !method.is_closure(&self.heap) {
+                        return Err(self.runtime_error("Methods must be functions."));
+                    }
+                    let (method, class) = (method.as_obj().unwrap(), class.as_obj().unwrap());
+                    self.heap.as_class_mut(class).methods.insert(name, method);
+                    self.pop()?;
+                }
+                OpCode::OpInvoke | OpCode::OpInvokeLong => {
+                    let method = self.read_string(instruction);
+                    let arg_count = self.read_byte() as usize;
+                    self.invoke(method, arg_count)?;
+                }
+                OpCode::OpInherit => {
+                    let (subclass, superclass) = (self.peek(0)?, self.peek(1)?);
+                    if !superclass.is_class(&self.heap) {
+                        return Err(self.runtime_error("Superclass must be a class."));
+                    }
+                    if !subclass.is_class(&self.heap) {
+                        return Err(self.runtime_error("Only classes can inherit."));
+                    }
+
+                    // copy-down inheritance, methods defined later in the subclass override these
+                    let methods = self.heap.as_class(superclass.as_obj().unwrap()).methods.clone();
+                    self.heap.as_class_mut(subclass.as_obj().unwrap()).methods.extend(methods);
+                    self.pop()?;
+                }
+                OpCode::OpGetSuper | OpCode::OpGetSuperLong => {
+                    let name = self.read_string(instruction);
+                    let superclass = self.superclass()?;
+                    self.bind_method(superclass, name)?;
+                }
+                OpCode::OpSuperInvoke | OpCode::OpSuperInvokeLong => {
+                    let method = self.read_string(instruction);
+                    let arg_count = self.read_byte() as usize;
+                    let superclass = self.superclass()?;
+                    self.invoke_from_class(superclass, method, arg_count)?;
                 }
                 OpCode::OpReturn => {
-                    let x = self.stack.pop();
-                    println!("'{}'", x.unwrap());
-                    return InterpretResult::InterpretOk;
+                    let result = self.pop()?;
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    self.stack.push(result);
+                    if self.frames.len() == base {
+                        return Ok(());
+                    }
                 }
             }
         }
     }
-    fn increment_counter(&mut self) {
-        self.pc += 1;
+    /// prints the stack and the instruction about to run
+    fn trace_instruction(&mut self) -> io::Result<()> {
+        let out = self.settings.trace_output.as_mut();
+        write!(out, "          ")?;
+        for value in self.stack.iter() {
+            write!(out, "[ {} ]", value.display(&self.heap))?;
+        }
+        writeln!(out)?;
+
+        let frame = self.frames.last().unwrap();
+        let chunk = &self.heap.as_function(frame.function).chunk;
+        Disassembler::disassemble_instruction(out, chunk, &self.heap, frame.pc)?;
+        Ok(())
+    }
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+    /// the chunk of the function that is currently executing
+    fn chunk(&self) -> &Chunk {
+        &self.heap.as_function(self.frame().function).chunk
+    }
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().unwrap();
+        let byte = self.heap.as_function(frame.function).chunk.code[frame.pc];
+        frame.pc += 1;
+        byte
+    }
+    /// reads a big endian 16 bit operand
+    fn read_short(&mut self) -> u16 {
+        let high = self.read_byte() as u16;
+        let low = self.read_byte() as u16;
+        (high << 8) | low
+    }
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_byte() as usize;
+        self.chunk().constants[index]
+    }
+    /// reads the big endian 24 bit index of a long form such as `OpConstantLong`
+    fn read_constant_long(&mut self) -> Value {
+        let high = self.read_byte() as usize;
+        let index = (high << 16) | self.read_short() as usize;
+        self.chunk().constants[index]
+    }
+    /// reads the constant operand of `instruction`, three bytes wide for the long forms
+    fn read_operand(&mut self, instruction: OpCode) -> Value {
+        if instruction.is_long() {
+            self.read_constant_long()
+        } else {
+            self.read_constant()
+        }
+    }
+    /// pops the class a `super` access looks methods up in
+    fn superclass(&mut self) -> Result<ObjRef, RuntimeError> {
+        let superclass = self.pop()?;
+        // only a loaded file can put anything else here
+        if !superclass.is_class(&self.heap) {
+            return Err(self.runtime_error("Superclass must be a class."));
+        }
+        Ok(superclass.as_obj().unwrap())
+    }
+    /// reads a constant operand and returns the string it points at
+    fn read_string(&mut self, instruction: OpCode) -> ObjRef {
+        self.read_operand(instruction).as_obj().unwrap()
+    }
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), RuntimeError> {
+        if let Ok(reference) = callee.as_obj() {
+            match self.heap.get(reference) {
+                Obj::Closure(_) => return self.call(reference, arg_count),
+                Obj::Native(native) => {
+                    let (arity, function) = (native.arity, native.function);
+                    if arg_count != arity {
+                        let message = format!("Expected {} arguments but got {}.", arity, arg_count);
+                        return Err(self.runtime_error(&message));
+                    }
+
+                    let args: Vec<Value> = self.stack[self.stack.len() - arg_count..].to_vec();
+                    return match function(self, &args) {
+                        Ok(result) => {
+                            self.stack.truncate(self.stack.len() - arg_count - 1);
+                            self.stack.push(result);
+                            Ok(())
+                        }
+                        Err(error) => Err(self.runtime_error(&error.message)),
+                    };
+                }
+                Obj::BoundMethod(bound) => {
+                    let method = bound.method;
+                    let slot = self.stack.len() - arg_count - 1;
+                    self.stack[slot] = bound.receiver;
+                    return self.call(method, arg_count);
+                }
+                Obj::Class(_) => {
+                    let instance = self.alloc(Obj::Instance(ObjInstance::new(reference)));
+                    let slot = self.stack.len() - arg_count - 1;
+                    self.stack[slot] = Value::from_obj(instance);
+
+                    let initializer = self.heap.as_class(reference).methods.get(&self.init_string).copied();
+                    return match initializer {
+                        Some(initializer) => self.call(initializer, arg_count),
+                        None if arg_count != 0 => {
+                            let message = format!("Expected 0 arguments but got {}.", arg_count);
+                            Err(self.runtime_error(&message))
+                        }
+                        None => Ok(()),
+                    };
+                }
+                _ => {}
+            }
+        }
+        Err(self.runtime_error("Can only call functions and classes."))
+    }
+    /// calls a method on the receiver sitting below the arguments, skipping the bound method
+    fn invoke(&mut self, name: ObjRef, arg_count: usize) -> Result<(), RuntimeError> {
+        let receiver = self.peek(arg_count)?;
+        if !receiver.is_instance(&self.heap) {
+            return Err(self.runtime_error("Only instances have methods."));
+        }
+        let instance = self.heap.as_instance(receiver.as_obj().unwrap());
+
+        // a field holding a function is called like any other value
+        if let Some(&field) = instance.fields.get(&name) {
+            let slot = self.stack.len() - arg_count - 1;
+            self.stack[slot] = field;
+            return self.call_value(field, arg_count);
+        }
+
+        let class = instance.class;
+        self.invoke_from_class(class, name, arg_count)
+    }
+    fn invoke_from_class(&mut self, class: ObjRef, name: ObjRef, arg_count: usize) -> Result<(), RuntimeError> {
+        match self.heap.as_class(class).methods.get(&name) {
+            Some(&method) => self.call(method, arg_count),
+            None => {
+                let message = format!("Undefined property '{}'.", self.heap.as_string(name).chars);
+                Err(self.runtime_error(&message))
+            }
+        }
+    }
+    /// replaces the instance on top of the stack with its method `name` bound to it
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> Result<(), RuntimeError> {
+        let method = match self.heap.as_class(class).methods.get(&name) {
+            Some(&method) => method,
+            None => {
+                let message = format!("Undefined property '{}'.", self.heap.as_string(name).chars);
+                return Err(self.runtime_error(&message));
+            }
+        };
+
+        let receiver = self.peek(0)?;
+        let bound = self.alloc(Obj::BoundMethod(ObjBoundMethod::new(receiver, method)));
+        self.pop()?;
+        self.stack.push(Value::from_obj(bound));
+        Ok(())
+    }
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> Result<(), RuntimeError> {
+        let function = self.heap.as_closure(closure).function;
+        let arity = self.heap.as_function(function).arity;
+        if arg_count != arity {
+            let message = format!("Expected {} arguments but got {}.", arity, arg_count);
+            return Err(self.runtime_error(&message));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            pc: 0,
+            // the callee itself sits in slot zero, just below the arguments
+            slots: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.runtime_error("Stack underflow.")),
+        }
     }
-    fn binary(&mut self, opp: &str) {
-        let b = self.stack.pop().unwrap();
-        let a = self.stack.pop().unwrap();
+    fn peek(&mut self, distance: usize) -> Result<Value, RuntimeError> {
+        if distance >= self.stack.len() {
+            return Err(self.runtime_error("Stack underflow."));
+        }
+        Ok(self.stack[self.stack.len() - 1 - distance])
+    }
+    fn binary(&mut self, opp: &str) -> Result<(), RuntimeError> {
+        let (b, a) = (self.peek(0)?, self.peek(1)?);
+        if opp == "add" && a.is_string(&self.heap) && b.is_string(&self.heap) {
+            return self.concatenate();
+        }
+        if opp == "add" && (!a.is_number() || !b.is_number()) {
+            return Err(self.runtime_error("Operands must be two numbers or two strings."));
+        }
+        if !a.is_number() || !b.is_number() {
+            return Err(self.runtime_error("Operands must be numbers."));
+        }
+        let b = self.pop()?.as_float().unwrap();
+        let a = self.pop()?.as_float().unwrap();
         match opp {
-            "add" => self.stack.push(a + b),
-            "sub" => self.stack.push(a - b),
-            "mult" => self.stack.push(a * b),
-            "divide" => self.stack.push(a / b),
-            "mod" => self.stack.push(a % b),
+            "add" => self.stack.push(Value::from_float(a + b)),
+            "sub" => self.stack.push(Value::from_float(a - b)),
+            "mult" => self.stack.push(Value::from_float(a * b)),
+            "divide" => self.stack.push(Value::from_float(a / b)),
+            "mod" => self.stack.push(Value::from_float(a % b)),
+            "greater" => self.stack.push(Value::from_bool(a > b)),
+            "less" => self.stack.push(Value::from_bool(a < b)),
             &_ => println!("unknown operator"),
         }
+        Ok(())
+    }
+    /// reuses the open upvalue for `location` if a closure already captured it
+    fn capture_upvalue(&mut self, location: usize) -> ObjRef {
+        let position = self.open_upvalues.iter().rposition(|&upvalue| self.heap.as_upvalue(upvalue).location <= location);
+        if let Some(position) = position {
+            let upvalue = self.open_upvalues[position];
+            if self.heap.as_upvalue(upvalue).location == location {
+                return upvalue;
+            }
+        }
+
+        let created = self.alloc(Obj::Upvalue(ObjUpvalue::new(location)));
+        let insert_at = position.map_or(0, |position| position + 1);
+        self.open_upvalues.insert(insert_at, created);
+        created
+    }
+    /// moves every captured variable at or above `last` off the stack
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(&upvalue) = self.open_upvalues.last() {
+            let location = self.heap.as_upvalue(upvalue).location;
+            if location < last {
+                break;
+            }
+            self.heap.as_upvalue_mut(upvalue).closed = Some(self.stack[location]);
+            self.open_upvalues.pop();
+        }
+    }
+    fn concatenate(&mut self) -> Result<(), RuntimeError> {
+        let b = self.pop()?.as_obj().unwrap();
+        let a = self.pop()?.as_obj().unwrap();
+
+        let mut chars = self.heap.as_string(a).chars.clone();
+        chars.push_str(&self.heap.as_string(b).chars);
+
+        if self.settings.stress_gc || self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        let result = self.heap.take_string(chars);
+        self.stack.push(Value::from_obj(result));
+        Ok(())
+    }
+    /// every runtime allocation goes through here so the collector gets a chance to run first
+    fn alloc(&mut self, obj: Obj) -> ObjRef {
+        if self.settings.stress_gc || self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(obj)
+    }
+    fn collect_garbage(&mut self) {
+        self.mark_roots();
+        self.heap.trace_references();
+        self.heap.sweep();
+    }
+    fn mark_roots(&mut self) {
+        for &value in self.stack.iter() {
+            self.heap.mark_value(value);
+        }
+        for (&name, &value) in self.globals.iter() {
+            self.heap.mark_object(name);
+            self.heap.mark_value(value);
+        }
+        for frame in self.frames.iter() {
+            self.heap.mark_object(frame.closure);
+        }
+        for &upvalue in self.open_upvalues.iter() {
+            self.heap.mark_object(upvalue);
+        }
+        self.heap.mark_object(self.init_string);
+        self.heap.mark_held();
+    }
+    /// captures where the error happened and resets the vm so it can run again
+    fn runtime_error(&mut self, message: &str) -> RuntimeError {
+        // taken before the depth cut, so the error keeps its line even without a backtrace
+        let line = self.frames.last().map_or(0, |frame| self.frame_line(frame));
+        let backtrace: Vec<TraceFrame> = self.frames.iter().rev().take(self.settings.backtrace_depth).map(|frame| {
+            let function = self.heap.as_function(frame.function);
+            TraceFrame {
+                function: function.name.map(|name| self.heap.as_string(name).chars.clone()),
+                line: self.frame_line(frame),
+            }
+        }).collect();
+
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+
+        RuntimeError {
+            message: message.to_string(),
+            line,
+            backtrace,
+        }
+    }
+
+    /// the line `frame` is running
+    fn frame_line(&self, frame: &CallFrame) -> u64 {
+        // pc already moved past the failing instruction
+        self.heap.as_function(frame.function).chunk.line_at(frame.pc.saturating_sub(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// collects before every allocation, so anything the vm forgets to root is freed
+    /// while it is still in use
+    #[test]
+    fn scripts_survive_a_collection_on_every_allocation() {
+        let source = "
+            class Node {
+                init(label, next) { this.label = label; this.next = next; }
+                describe() {
+                    if (this.next == nil) return this.label;
+                    return this.label + \" -> \" + this.next.describe();
+                }
+            }
+            class Tagged < Node {
+                describe() { return \"[\" + super.describe() + \"]\"; }
+            }
+            func joiner(separator) {
+                dec joined = \"\";
+                func add(part) {
+                    if (joined == \"\") joined = part; else joined = joined + separator + part;
+                    return joined;
+                }
+                return add;
+            }
+
+            dec add = joiner(\", \");
+            dec list = nil;
+            for (dec i = 0; i < 5; i = i + 1) {
+                dec label = \"n\" + \"ode\";
+                list = Node(label, list);
+                add(label);
+            }
+            print Tagged(\"head\", list).describe();
+            dec describe = list.describe;
+            print describe();
+            print add(\"done\");
+        ";
+        let settings = Settings::builder().stress_gc(true).capture_output().build();
+        let mut vm = VM::with_settings(Heap::new(), settings);
+        let function = vm.compile(source.to_string()).unwrap();
+
+        assert!(matches!(vm.interpret(function), InterpretResult::InterpretOk));
+        assert_eq!(
+            vm.captured_output().unwrap(),
+            "[head -> node -> node -> node -> node -> node]\n\
+             node -> node -> node -> node -> node\n\
+             node, node, node, node, node, done\n"
+        );
+    }
+
+    #[test]
+    fn runtime_errors_keep_their_line_without_a_backtrace() {
+        let settings = Settings::builder().backtrace_depth(0).capture_output().build();
+        let mut vm = VM::with_settings(Heap::new(), settings);
+        let function = vm.compile("print 1;\n\nprint -\"one\";".to_string()).unwrap();
+
+        match vm.interpret(function) {
+            InterpretResult::InterpretRuntimeError(error) => {
+                assert_eq!(error.line, 3);
+                assert!(error.backtrace.is_empty());
+            }
+            _ => panic!("expected a runtime error"),
+        }
     }
 }